@@ -0,0 +1,227 @@
+use crate::tile_scheduler::Tile;
+use crate::{math::*, BaseMaterial, CropWindow, GeometryHitResult};
+
+// What the first thing that a camera ray hits looks like, for one sample
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AovSample {
+    pub time: FloatType,
+    pub hit: Option<AovHit>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AovHit {
+    pub depth: FloatType,
+    pub normal: Vector3,
+    pub albedo: Vector3,
+    pub uv: Point2,
+    pub shape: usize,
+}
+
+impl AovHit {
+    pub fn new(shape: usize, hit_result: &GeometryHitResult, material: &dyn BaseMaterial) -> Self {
+        Self {
+            depth: hit_result.distance,
+            normal: material.base_shading_normal(hit_result),
+            albedo: material.base_albedo(hit_result).into(),
+            uv: hit_result.uv,
+            shape,
+        }
+    }
+}
+
+// The sums of everything we know about the first hits in a pixel. Depth, normal, albedo and uv
+// are only added up for the samples that hit something, and are averaged over those, whereas the
+// time comes from every sample. Ids can't be averaged, so the object id comes from the first
+// sample in the pixel that hit something, going by pass order. It is the index of the shape plus
+// one, so that zero means nothing was hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AovPixel {
+    pub samples: FloatType,
+    pub hits: FloatType,
+    pub depth: FloatType,
+    pub normal: Vector3,
+    pub albedo: Vector3,
+    pub uv: Vector2,
+    pub time: FloatType,
+    pub object_id: u32,
+}
+
+impl Default for AovPixel {
+    fn default() -> Self {
+        Self {
+            samples: 0.0,
+            hits: 0.0,
+            depth: 0.0,
+            normal: Vector3::zero(),
+            albedo: Vector3::zero(),
+            uv: Vector2::zero(),
+            time: 0.0,
+            object_id: 0,
+        }
+    }
+}
+
+impl AovPixel {
+    pub(crate) fn add_sample(&mut self, sample: &AovSample) {
+        self.samples += 1.0;
+        self.time += sample.time;
+
+        if let Some(hit) = &sample.hit {
+            self.hits += 1.0;
+            self.depth += hit.depth;
+            self.normal += hit.normal;
+            self.albedo += hit.albedo;
+            self.uv += hit.uv.to_vec();
+            if self.object_id == 0 {
+                self.object_id = (hit.shape as u32) + 1;
+            }
+        }
+    }
+
+    pub(crate) fn add(&mut self, other: &AovPixel) {
+        self.samples += other.samples;
+        self.hits += other.hits;
+        self.depth += other.depth;
+        self.normal += other.normal;
+        self.albedo += other.albedo;
+        self.uv += other.uv;
+        self.time += other.time;
+
+        // Other is always from later passes, so its first hit only counts if we haven't had one
+        if self.object_id == 0 {
+            self.object_id = other.object_id;
+        }
+    }
+}
+
+// One kind of auxiliary output, averaged and ready to write out, with one value per pixel for
+// each channel
+pub struct AovLayer {
+    pub name: &'static str,
+    pub channels: Vec<(&'static str, Vec<FloatType>)>,
+}
+
+#[derive(Clone)]
+pub struct AovImage {
+    width: usize,
+    data: Box<[AovPixel]>,
+}
+
+impl AovImage {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            data: vec![AovPixel::default(); width * height].into_boxed_slice(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.data.len().checked_div(self.width).unwrap_or(0)
+    }
+
+    pub fn pixels(&self) -> &[AovPixel] {
+        &self.data
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [AovPixel] {
+        &mut self.data
+    }
+
+    pub fn crop(&self, window: CropWindow) -> AovImage {
+        let window = window.clip(self.width(), self.height());
+        Self {
+            width: window.width,
+            data: window.crop(&self.data, self.width),
+        }
+    }
+
+    pub(crate) fn add_tile(&mut self, tile: &Tile, tile_data: &[AovPixel]) {
+        debug_assert_eq!(tile_data.len(), tile.pixel_count());
+
+        for ((x, y), src) in tile.pixels().zip(tile_data) {
+            self.data[(y * self.width) + x].add(src);
+        }
+    }
+
+    pub fn layers(&self) -> Vec<AovLayer> {
+        let per_hit = |f: fn(&AovPixel) -> FloatType| -> Vec<FloatType> {
+            self.data
+                .iter()
+                .map(|pixel| {
+                    if pixel.hits > 0.0 {
+                        f(pixel) / pixel.hits
+                    } else {
+                        0.0
+                    }
+                })
+                .collect()
+        };
+
+        vec![
+            AovLayer {
+                name: "depth",
+                channels: vec![("Z", per_hit(|p| p.depth))],
+            },
+            AovLayer {
+                name: "normal",
+                channels: vec![
+                    ("X", per_hit(|p| p.normal.x)),
+                    ("Y", per_hit(|p| p.normal.y)),
+                    ("Z", per_hit(|p| p.normal.z)),
+                ],
+            },
+            AovLayer {
+                name: "albedo",
+                channels: vec![
+                    ("R", per_hit(|p| p.albedo.x)),
+                    ("G", per_hit(|p| p.albedo.y)),
+                    ("B", per_hit(|p| p.albedo.z)),
+                ],
+            },
+            AovLayer {
+                name: "uv",
+                channels: vec![("U", per_hit(|p| p.uv.x)), ("V", per_hit(|p| p.uv.y))],
+            },
+            AovLayer {
+                name: "time",
+                channels: vec![(
+                    "T",
+                    self.data
+                        .iter()
+                        .map(|p| {
+                            if p.samples > 0.0 {
+                                p.time / p.samples
+                            } else {
+                                0.0
+                            }
+                        })
+                        .collect(),
+                )],
+            },
+            AovLayer {
+                name: "id",
+                channels: vec![(
+                    "id",
+                    self.data.iter().map(|p| p.object_id as FloatType).collect(),
+                )],
+            },
+        ]
+    }
+}
+
+impl std::ops::Add for AovImage {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        self.data
+            .iter_mut()
+            .zip(other.data.iter())
+            .for_each(|(dst, src)| dst.add(src));
+
+        self
+    }
+}
@@ -1,14 +1,149 @@
-use crate::{math::*, utils::*, Ray};
+use crate::{math::*, Aperture, Named, PhysicalLens, Ray, Sampler, Shutter};
+use anyhow::{anyhow, Result};
+
+// How a fisheye lens spreads angles out over the image. Equidistant lenses keep the distance from
+// the center in proportion to the angle away from the view direction, and equisolid ones keep
+// areas in proportion to solid angles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FisheyeMapping {
+    Equidistant,
+    Equisolid,
+}
+
+impl Named for FisheyeMapping {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("equidistant", FisheyeMapping::Equidistant),
+        ("equisolid", FisheyeMapping::Equisolid),
+    ];
+}
+
+// How the two eyes of a stereo rig share the image. The left eye goes on the left, or on the top.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StereoLayout {
+    SideBySide,
+    OverUnder,
+}
+
+impl Named for StereoLayout {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("side-by-side", StereoLayout::SideBySide),
+        ("over-under", StereoLayout::OverUnder),
+    ];
+}
+
+// Renders both eyes at once, each into its own half of the image. The eyes are the interocular
+// distance apart, and turn in so that they both look at the same point at the convergence
+// distance, or look straight ahead if there isn't one. The camera should be made for the size of
+// one eye, which eye_size gives.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StereoRig {
+    pub interocular: FloatType,
+    pub convergence: Option<FloatType>,
+    pub layout: StereoLayout,
+}
+
+impl StereoRig {
+    pub fn eye_size(&self, width: usize, height: usize) -> (usize, usize) {
+        match self.layout {
+            StereoLayout::SideBySide => (width / 2, height),
+            StereoLayout::OverUnder => (width, height / 2),
+        }
+    }
+
+    // Which eye a point on the whole image belongs to, as how far it is moved sideways, and
+    // where the point is on that eye's half
+    fn eye(&self, s: FloatType, t: FloatType) -> (FloatType, FloatType, FloatType) {
+        let half = self.interocular / 2.0;
+        match self.layout {
+            StereoLayout::SideBySide if s < 0.5 => (-half, s * 2.0, t),
+            StereoLayout::SideBySide => (half, (s * 2.0) - 1.0, t),
+            StereoLayout::OverUnder if t >= 0.5 => (-half, s, (t * 2.0) - 1.0),
+            StereoLayout::OverUnder => (half, s, t * 2.0),
+        }
+    }
+}
+
+// Where a perspective camera is, and how it is set up, at one moment. Cameras with more than one
+// of these move between them in straight lines, and stay at the first and last before and after
+// them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraKeyframe {
+    pub time: FloatType,
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vector3,
+    pub fov: Rad<FloatType>,
+    pub aperture: FloatType,
+    pub focus_dist: FloatType,
+}
+
+impl CameraKeyframe {
+    fn lerp(&self, other: &CameraKeyframe, amount: FloatType) -> CameraKeyframe {
+        let lerp = |a: FloatType, b: FloatType| a + ((b - a) * amount);
+        CameraKeyframe {
+            time: lerp(self.time, other.time),
+            lookfrom: self.lookfrom + ((other.lookfrom - self.lookfrom) * amount),
+            lookat: self.lookat + ((other.lookat - self.lookat) * amount),
+            vup: self.vup.lerp(other.vup, amount),
+            fov: Rad(lerp(self.fov.0, other.fov.0)),
+            aperture: lerp(self.aperture, other.aperture),
+            focus_dist: lerp(self.focus_dist, other.focus_dist),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Animation {
+    keyframes: Vec<CameraKeyframe>,
+    aspect_ratio: FloatType,
+}
+
+impl Animation {
+    fn at(&self, time: FloatType) -> CameraKeyframe {
+        let next = self.keyframes.iter().position(|key| key.time > time);
+        match next {
+            Some(0) => self.keyframes[0],
+            Some(next) => {
+                let (before, after) = (&self.keyframes[next - 1], &self.keyframes[next]);
+                before.lerp(after, (time - before.time) / (after.time - before.time))
+            }
+            None => self.keyframes[self.keyframes.len() - 1],
+        }
+    }
+}
+
+// Perspective rays all leave from the lens and spread out through the viewport, whereas
+// orthographic rays leave from the viewport itself and all go the same way. Panoramic rays leave
+// from the camera position and can go in any direction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Projection {
+    Perspective,
+    Orthographic,
+    Equirectangular,
+    Fisheye {
+        mapping: FisheyeMapping,
+        fov: FloatType,
+        aspect_ratio: FloatType,
+    },
+}
 
 #[derive(Clone, Debug)]
 pub struct Camera {
+    projection: Projection,
     origin: Point3,
     lower_left_corner: Point3,
     horizontal: Vector3,
     vertical: Vector3,
     u: Vector3,
     v: Vector3,
+    w: Vector3,
     lens_radius: FloatType,
+    eye_offset: FloatType,
+    stereo: Option<StereoRig>,
+    animation: Option<Animation>,
+    aperture: Aperture,
+    exposure: FloatType,
+    shutter: Shutter,
 }
 
 impl Camera {
@@ -35,15 +170,255 @@ impl Camera {
         let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - focus_dist * w;
 
         Self {
+            projection: Projection::Perspective,
             origin,
             horizontal,
             vertical,
             lower_left_corner,
             u,
             v,
+            w,
             lens_radius: aperture / 2.0,
+            eye_offset: 0.0,
+            stereo: None,
+            animation: None,
+            aperture: Aperture::default(),
+            exposure: 1.0,
+            shutter: Shutter::default(),
+        }
+    }
+
+    // A perspective camera set up like a real one, which also sets how bright the image is
+    pub fn physical(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vector3,
+        lens: &PhysicalLens,
+        focus_dist: FloatType,
+    ) -> Self {
+        Self {
+            exposure: lens.exposure(),
+            ..Self::new(
+                lookfrom,
+                lookat,
+                vup,
+                lens.fov(),
+                lens.aspect_ratio(),
+                lens.aperture_diameter(),
+                focus_dist,
+            )
+        }
+    }
+
+    // A perspective camera that moves, and can change its lens, between the keyframes over time.
+    // Rays are made by the camera as it is at the time they are for, so anything that moves
+    // during the shutter is blurred.
+    pub fn animated(
+        keyframes: impl IntoIterator<Item = CameraKeyframe>,
+        aspect_ratio: FloatType,
+    ) -> Result<Self> {
+        let mut keyframes: Vec<_> = keyframes.into_iter().collect();
+        if let Some(key) = keyframes.iter().find(|key| !key.time.is_finite()) {
+            return Err(anyhow!(
+                "Camera keyframe times must be finite, but one is at {}",
+                key.time
+            ));
+        }
+        keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+
+        let first = *keyframes
+            .first()
+            .ok_or_else(|| anyhow!("An animated camera needs at least one keyframe"))?;
+        let camera = Self::from_keyframe(&first, aspect_ratio);
+        Ok(if keyframes.len() > 1 {
+            Self {
+                animation: Some(Animation {
+                    keyframes,
+                    aspect_ratio,
+                }),
+                ..camera
+            }
+        } else {
+            camera
+        })
+    }
+
+    fn from_keyframe(key: &CameraKeyframe, aspect_ratio: FloatType) -> Self {
+        Self::new(
+            key.lookfrom,
+            key.lookat,
+            key.vup,
+            key.fov,
+            aspect_ratio,
+            key.aperture,
+            key.focus_dist,
+        )
+    }
+
+    // The camera as it is at a moment of an animation. Only the view and the lens change, and
+    // everything else, such as the stereo setup and the shape of the aperture, always comes from
+    // the animated camera.
+    fn at(animation: &Animation, time: FloatType) -> Self {
+        Self::from_keyframe(&animation.at(time), animation.aspect_ratio)
+    }
+
+    // The view width and height are the size of the area the image covers, in scene units. There
+    // is no lens, so everything is in focus.
+    pub fn orthographic(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vector3,
+        view_width: FloatType,
+        view_height: FloatType,
+    ) -> Self {
+        let w = (lookfrom - lookat).normalize();
+        let u = vup.cross(w).normalize();
+        let v = w.cross(u);
+
+        let origin = lookfrom;
+        let horizontal = view_width * u;
+        let vertical = view_height * v;
+        let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0;
+
+        Self {
+            projection: Projection::Orthographic,
+            origin,
+            horizontal,
+            vertical,
+            lower_left_corner,
+            u,
+            v,
+            w,
+            lens_radius: 0.0,
+            eye_offset: 0.0,
+            stereo: None,
+            animation: None,
+            aperture: Aperture::default(),
+            exposure: 1.0,
+            shutter: Shutter::default(),
+        }
+    }
+
+    // Longitude goes across the image and latitude up it, so the image covers every direction,
+    // with the look at point in the middle
+    pub fn equirectangular(lookfrom: Point3, lookat: Point3, vup: Vector3) -> Self {
+        Self::panoramic(Projection::Equirectangular, lookfrom, lookat, vup)
+    }
+
+    // The field of view is measured across the height of the image, as it is for perspective
+    // cameras, and can go up to 360 degrees. Anything outside of it is black.
+    pub fn fisheye(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vector3,
+        fov: Rad<FloatType>,
+        aspect_ratio: FloatType,
+        mapping: FisheyeMapping,
+    ) -> Self {
+        let projection = Projection::Fisheye {
+            mapping,
+            fov: fov.0.clamp(0.0, 2.0 * constants::PI),
+            aspect_ratio,
+        };
+        Self::panoramic(projection, lookfrom, lookat, vup)
+    }
+
+    fn panoramic(projection: Projection, lookfrom: Point3, lookat: Point3, vup: Vector3) -> Self {
+        let w = (lookfrom - lookat).normalize();
+        let u = vup.cross(w).normalize();
+        let v = w.cross(u);
+
+        Self {
+            projection,
+            origin: lookfrom,
+            lower_left_corner: lookfrom,
+            horizontal: Vector3::zero(),
+            vertical: Vector3::zero(),
+            u,
+            v,
+            w,
+            lens_radius: 0.0,
+            eye_offset: 0.0,
+            stereo: None,
+            animation: None,
+            aperture: Aperture::default(),
+            exposure: 1.0,
+            shutter: Shutter::default(),
         }
     }
+
+    // Moves the camera sideways by this much, to the right for positive offsets, to give one eye
+    // of a stereo pair. Panoramic cameras move each ray sideways from the direction it goes in
+    // instead, which is omni-directional stereo, and the offset shrinks towards the poles so that
+    // the two eyes meet there.
+    pub fn with_eye_offset(mut self, eye_offset: FloatType) -> Self {
+        self.eye_offset = eye_offset;
+        self
+    }
+
+    pub fn with_stereo(mut self, stereo: StereoRig) -> Self {
+        self.stereo = Some(stereo);
+        self
+    }
+
+    // The aperture only makes a difference to cameras that have one, so not to pinholes,
+    // orthographic or panoramic cameras
+    pub fn with_aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    // Controls when during the render's shutter interval each ray is for
+    pub fn with_shutter(mut self, shutter: Shutter) -> Self {
+        self.shutter = shutter;
+        self
+    }
+
+    // What everything the camera sees is multiplied by
+    pub fn exposure(&self) -> FloatType {
+        self.exposure
+    }
+
+    // The direction for a point on a panoramic image, or nothing if the point is outside the
+    // image circle of a fisheye
+    fn panoramic_direction(&self, s: FloatType, t: FloatType) -> Option<Vector3> {
+        let (theta, phi) = match self.projection {
+            Projection::Equirectangular => {
+                let longitude = (s - 0.5) * 2.0 * constants::PI;
+                let latitude = (t - 0.5) * constants::PI;
+                let horizontal = self.u * longitude.sin() - self.w * longitude.cos();
+                return Some(horizontal * latitude.cos() + self.v * latitude.sin());
+            }
+
+            Projection::Fisheye {
+                mapping,
+                fov,
+                aspect_ratio,
+            } => {
+                let (x, y) = ((s - 0.5) * 2.0 * aspect_ratio, (t - 0.5) * 2.0);
+                let radius = (x * x + y * y).sqrt();
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => radius * fov / 2.0,
+                    FisheyeMapping::Equisolid => {
+                        let sin_half_theta = radius * (fov / 4.0).sin();
+                        if sin_half_theta > 1.0 {
+                            return None;
+                        }
+                        2.0 * sin_half_theta.asin()
+                    }
+                };
+                if theta > fov / 2.0 {
+                    return None;
+                }
+                (theta, y.atan2(x))
+            }
+
+            Projection::Perspective | Projection::Orthographic => unreachable!(),
+        };
+
+        let sideways = self.u * phi.cos() + self.v * phi.sin();
+        Some(sideways * theta.sin() - self.w * theta.cos())
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -58,15 +433,193 @@ impl PreparedCamera {
         Self { camera, t0, t1 }
     }
 
-    pub fn make_ray(&self, s: FloatType, t: FloatType) -> Ray {
-        let rd = self.camera.lens_radius * random_in_unit_disk();
-        let offset = self.camera.u * rd.x + self.camera.v * rd.y;
+    pub fn exposure(&self) -> FloatType {
+        self.camera.exposure
+    }
+
+    // There is no ray for points that the camera can't see, such as the corners of a fisheye
+    // image, and those are left black
+    pub fn make_ray(&self, s: FloatType, t: FloatType, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let (eye_offset, s, t, convergence) = match self.camera.stereo {
+            Some(stereo) => {
+                let (eye_offset, s, t) = stereo.eye(s, t);
+                (
+                    self.camera.eye_offset + eye_offset,
+                    s,
+                    t,
+                    stereo.convergence,
+                )
+            }
+            None => (self.camera.eye_offset, s, t, None),
+        };
+
+        let lens = self.camera.aperture.sample(sampler);
+        let time = self
+            .camera
+            .shutter
+            .time(sampler.next_1d(), self.t0, self.t1, t);
 
-        let direction =
-            self.camera.lower_left_corner + s * self.camera.horizontal + t * self.camera.vertical
-                - self.camera.origin
-                - offset;
-        let time = random_in_range(self.t0, self.t1);
-        Ray::new(self.camera.origin + offset, direction.normalize(), time)
+        let animated;
+        let camera = match &self.camera.animation {
+            Some(animation) => {
+                animated = Camera::at(animation, time);
+                &animated
+            }
+            None => &self.camera,
+        };
+
+        if camera.lens_radius > 0.0
+            && !self
+                .camera
+                .aperture
+                .passes(lens, (2.0 * s) - 1.0, (2.0 * t) - 1.0)
+        {
+            return None;
+        }
+
+        let rd = camera.lens_radius * lens;
+        let offset = camera.u * rd.x + camera.v * rd.y;
+        let eye = camera.u * eye_offset;
+
+        // Eyes turn in by aiming at where the ray from the middle of the camera would be at the
+        // convergence distance
+        let viewport_point = camera.lower_left_corner + s * camera.horizontal + t * camera.vertical;
+        let (origin, direction) = match camera.projection {
+            Projection::Perspective => {
+                let toe_in = match convergence {
+                    Some(convergence) => {
+                        let focus_dist = (camera.origin - viewport_point).dot(camera.w);
+                        eye * (focus_dist / convergence)
+                    }
+                    None => Vector3::zero(),
+                };
+                (
+                    camera.origin + eye + offset,
+                    viewport_point - camera.origin - offset - toe_in,
+                )
+            }
+            Projection::Orthographic => {
+                let direction = match convergence {
+                    Some(convergence) => (-camera.w * convergence) - eye,
+                    None => -camera.w,
+                };
+                (viewport_point + eye, direction)
+            }
+            Projection::Equirectangular | Projection::Fisheye { .. } => {
+                let direction = camera.panoramic_direction(s, t)?;
+
+                // Only the part of the direction that is level with the camera moves the eye,
+                // which is what makes the offset fade out towards the poles
+                let level = direction - camera.v * direction.dot(camera.v);
+                let eye = level.cross(camera.v) * eye_offset;
+                let direction = match convergence {
+                    Some(convergence) => (direction * convergence) - eye,
+                    None => direction,
+                };
+                (camera.origin + eye, direction)
+            }
+        };
+
+        Some(Ray::new(origin, direction.normalize(), time))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SamplerKind;
+
+    #[test]
+    fn test_camera_rays() {
+        let mut sampler = SamplerKind::Random.sampler(0, 0, 0, 1);
+        let lookfrom = point3(0.0, 0.0, 0.0);
+        let lookat = point3(0.0, 0.0, -1.0);
+        let vup = vec3(0.0, 1.0, 0.0);
+        let close = |a: Vector3, b: Vector3| (a - b).magnitude() < 0.0001;
+
+        // The middle of a panorama looks at the look at point, the edges look behind and the top
+        // looks straight up
+        let camera = PreparedCamera::make(
+            Camera::equirectangular(lookfrom, lookat, vup).with_eye_offset(0.1),
+            0.0,
+            1.0,
+        );
+        let ray = |s, t, sampler: &mut dyn Sampler| camera.make_ray(s, t, sampler).unwrap();
+        let middle = ray(0.5, 0.5, sampler.as_mut());
+        assert!(close(middle.direction(), vec3(0.0, 0.0, -1.0)));
+        assert!(close(middle.origin().to_vec(), vec3(0.1, 0.0, 0.0)));
+        assert!(close(
+            ray(0.0, 0.5, sampler.as_mut()).direction(),
+            vec3(0.0, 0.0, 1.0)
+        ));
+        assert!(close(
+            ray(0.75, 0.5, sampler.as_mut()).direction(),
+            vec3(1.0, 0.0, 0.0)
+        ));
+        assert!(close(
+            ray(0.5, 1.0, sampler.as_mut()).direction(),
+            vec3(0.0, 1.0, 0.0)
+        ));
+        assert!(close(
+            ray(0.5, 1.0, sampler.as_mut()).origin().to_vec(),
+            Vector3::zero()
+        ));
+
+        // A 180 degree fisheye reaches sideways at the edge of the image circle, and sees nothing
+        // in the corners
+        for (_, mapping) in FisheyeMapping::NAMES.iter() {
+            let camera = PreparedCamera::make(
+                Camera::fisheye(lookfrom, lookat, vup, Deg(180.0).into(), 1.0, *mapping),
+                0.0,
+                1.0,
+            );
+            let edge = camera.make_ray(0.5, 1.0, sampler.as_mut()).unwrap();
+            assert!(
+                close(edge.direction(), vec3(0.0, 1.0, 0.0)),
+                "{:?}",
+                mapping
+            );
+            assert!(camera.make_ray(1.0, 1.0, sampler.as_mut()).is_none());
+        }
+
+        // The middle of each half of a side by side stereo pair looks at the convergence point
+        // from one of the eyes
+        let stereo = StereoRig {
+            interocular: 0.2,
+            convergence: Some(10.0),
+            layout: StereoLayout::SideBySide,
+        };
+        let camera = PreparedCamera::make(
+            Camera::new(lookfrom, lookat, vup, Deg(40.0).into(), 1.0, 0.0, 1.0).with_stereo(stereo),
+            0.0,
+            1.0,
+        );
+        for (s, eye) in [(0.25, -0.1), (0.75, 0.1)].iter() {
+            let ray = camera.make_ray(*s, 0.5, sampler.as_mut()).unwrap();
+            assert!(close(ray.origin().to_vec(), vec3(*eye, 0.0, 0.0)));
+            assert!(close(ray.direction(), vec3(-*eye, 0.0, -10.0).normalize()));
+        }
+
+        // Animated cameras are wherever they are at the time of the ray
+        let keyframe = |time, x| CameraKeyframe {
+            time,
+            lookfrom: point3(x, 0.0, 0.0),
+            lookat: point3(x, 0.0, -1.0),
+            vup,
+            fov: Deg(40.0).into(),
+            aperture: 0.0,
+            focus_dist: 1.0,
+        };
+        let camera = Camera::animated(vec![keyframe(2.0, 4.0), keyframe(0.0, 0.0)], 1.0).unwrap();
+        for (time, x) in [(-1.0, 0.0), (0.5, 1.0), (1.0, 2.0), (3.0, 4.0)].iter() {
+            let camera = PreparedCamera::make(camera.clone(), *time, *time);
+            let ray = camera.make_ray(0.5, 0.5, sampler.as_mut()).unwrap();
+            assert!(close(ray.origin().to_vec(), vec3(*x, 0.0, 0.0)));
+            assert!(close(ray.direction(), vec3(0.0, 0.0, -1.0)));
+        }
+        assert!(Camera::animated(vec![], 1.0).is_err());
+        assert!(
+            Camera::animated(vec![keyframe(0.0, 0.0), keyframe(FloatType::NAN, 1.0)], 1.0).is_err()
+        );
     }
 }
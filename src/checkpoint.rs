@@ -0,0 +1,517 @@
+use crate::ray_scanner::SampleStats;
+use crate::{
+    math::*, AovImage, AovPixel, CropWindow, FilterKind, Named, PixelFilter, RenderOutput,
+    RenderSettings, SamplerKind, VectorImage,
+};
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::mem::size_of;
+use std::path::Path;
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RASTCKPT";
+const CHECKPOINT_VERSION: u32 = 7;
+
+// Scene and filter names are short, so anything longer than this means the file is damaged
+const MAX_STRING_LENGTH: usize = 4096;
+
+// Everything needed to carry on with a render that was stopped: the settings it was started with,
+// how far it got, and the sums it had accumulated by then. Checkpoints are only ever taken
+// between rounds, so carrying on traces exactly the samples the original render would have.
+pub struct RenderCheckpoint {
+    // Whatever the caller uses to tell scenes apart. The renderer doesn't look at it, but it is
+    // saved so that a render isn't resumed with a different scene.
+    pub scene_name: String,
+    // The fingerprint of the scene that was rendered, which unlike the name changes if the scene
+    // does. Renders can only be resumed or merged with the same scene.
+    pub scene_hash: u64,
+    pub settings: RenderSettings,
+    pub round: usize,
+    pub passes_done: usize,
+    pub(crate) image: VectorImage,
+    pub(crate) sample_stats: Box<[SampleStats]>,
+    pub(crate) aovs: Option<AovImage>,
+}
+
+impl RenderCheckpoint {
+    pub fn image(&self) -> &VectorImage {
+        &self.image
+    }
+
+    pub fn aovs(&self) -> Option<&AovImage> {
+        self.aovs.as_ref()
+    }
+
+    pub fn into_output(self) -> RenderOutput {
+        RenderOutput {
+            image: self.image,
+            aovs: self.aovs,
+            sample_stats: self.sample_stats,
+        }
+    }
+
+    pub fn image_size(&self) -> (usize, usize) {
+        (self.image.width(), self.image.height())
+    }
+
+    // Write to a temporary file first and then move it into place, so that dying part way
+    // through a save doesn't leave us without any checkpoint at all
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        self.write_to(&mut writer)?;
+        writer.into_inner()?.sync_all()?;
+
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        Self::read_from(&mut BufReader::new(file), size)
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<()> {
+        let (width, height) = self.image_size();
+        let settings = &self.settings;
+
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        write_u32(writer, CHECKPOINT_VERSION)?;
+        write_u32(writer, size_of::<FloatType>() as u32)?;
+
+        write_usize(writer, width)?;
+        write_usize(writer, height)?;
+        write_string(writer, &self.scene_name)?;
+        write_u64(writer, self.scene_hash)?;
+
+        write_usize(writer, settings.thread_count)?;
+        write_usize(writer, settings.min_passes)?;
+        write_option(writer, settings.noise_threshold, write_float)?;
+        write_usize(writer, settings.max_passes)?;
+        write_option(writer, settings.passes_per_round, write_usize)?;
+        write_usize(writer, settings.max_depth)?;
+        write_usize(writer, settings.min_depth)?;
+        write_bool(writer, settings.russian_roulette)?;
+        write_option(writer, settings.max_sample_luminance, write_float)?;
+        write_option(writer, settings.outlier_sigma, write_float)?;
+        write_u64(writer, settings.seed)?;
+        write_string(writer, settings.sampler.name())?;
+        write_string(writer, settings.filter.kind.name())?;
+        write_float(writer, settings.filter.radius)?;
+        write_option(writer, settings.crop, write_crop_window)?;
+        write_bool(writer, settings.aovs)?;
+
+        write_usize(writer, self.round)?;
+        write_usize(writer, self.passes_done)?;
+
+        for pixel in self.image.pixels() {
+            write_float(writer, pixel.x)?;
+            write_float(writer, pixel.y)?;
+            write_float(writer, pixel.z)?;
+            write_float(writer, pixel.w)?;
+        }
+        for sample_stats in self.sample_stats.iter() {
+            write_float(writer, sample_stats.count)?;
+            write_float(writer, sample_stats.luminance)?;
+            write_float(writer, sample_stats.luminance_squares)?;
+        }
+
+        write_bool(writer, self.aovs.is_some())?;
+        for pixel in self.aovs.iter().flat_map(|aovs| aovs.pixels()) {
+            write_aov_pixel(writer, pixel)?;
+        }
+
+        Ok(())
+    }
+
+    // The size is how many bytes the reader has in it, which the sizes in the checkpoint are
+    // checked against before anything is allocated for them
+    pub fn read_from(reader: &mut impl Read, size: u64) -> Result<Self> {
+        let reader = &mut BoundedReader {
+            inner: reader,
+            remaining: size,
+        };
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(anyhow!("Not a render checkpoint"));
+        }
+
+        let version = read_u32(reader)?;
+        if version != CHECKPOINT_VERSION {
+            return Err(anyhow!("Unsupported checkpoint version {}", version));
+        }
+
+        let float_size = read_u32(reader)? as usize;
+        if float_size != size_of::<FloatType>() {
+            return Err(anyhow!(
+                "Checkpoint has {} byte floats, expected {}",
+                float_size,
+                size_of::<FloatType>()
+            ));
+        }
+
+        let width = read_usize(reader)?;
+        let height = read_usize(reader)?;
+        let scene_name = read_string(reader)?;
+        let scene_hash = read_u64(reader)?;
+
+        let mut settings = RenderSettings::new(read_usize(reader)?, read_usize(reader)?);
+        settings.noise_threshold = read_option(reader, read_float)?;
+        settings.max_passes = read_usize(reader)?;
+        settings.passes_per_round = read_option(reader, read_usize)?;
+        settings.max_depth = read_usize(reader)?;
+        settings.min_depth = read_usize(reader)?;
+        settings.russian_roulette = read_bool(reader)?;
+        settings.max_sample_luminance = read_option(reader, read_float)?;
+        settings.outlier_sigma = read_option(reader, read_float)?;
+        settings.seed = read_u64(reader)?;
+
+        let sampler = read_string(reader)?;
+        settings.sampler = SamplerKind::from_name(&sampler)
+            .ok_or_else(|| anyhow!("Unknown sampler \"{}\" in checkpoint", sampler))?;
+
+        let filter = read_string(reader)?;
+        settings.filter = PixelFilter {
+            kind: FilterKind::from_name(&filter)
+                .ok_or_else(|| anyhow!("Unknown filter \"{}\" in checkpoint", filter))?,
+            radius: read_float(reader)?,
+        };
+        settings.crop = read_option(reader, read_crop_window)?;
+        settings.aovs = read_bool(reader)?;
+
+        let round = read_usize(reader)?;
+        let passes_done = read_usize(reader)?;
+
+        let pixel_count = width
+            .checked_mul(height)
+            .ok_or_else(|| anyhow!("Checkpoint size {}x{} is too large", width, height))?;
+        reader.check_remaining(pixel_count, 7 * size_of::<FloatType>())?;
+
+        let mut image = VectorImage::new(width, height);
+        for pixel in image.pixels_mut() {
+            *pixel = cgmath::vec4(
+                read_float(reader)?,
+                read_float(reader)?,
+                read_float(reader)?,
+                read_float(reader)?,
+            );
+        }
+        let sample_stats = (0..pixel_count)
+            .map(|_| {
+                Ok(SampleStats {
+                    count: read_float(reader)?,
+                    luminance: read_float(reader)?,
+                    luminance_squares: read_float(reader)?,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let aovs = if read_bool(reader)? {
+            reader.check_remaining(
+                pixel_count,
+                (12 * size_of::<FloatType>()) + size_of::<u32>(),
+            )?;
+            let mut aovs = AovImage::new(width, height);
+            for pixel in aovs.pixels_mut() {
+                *pixel = read_aov_pixel(reader)?;
+            }
+            Some(aovs)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            scene_name,
+            scene_hash,
+            settings,
+            round,
+            passes_done,
+            image,
+            sample_stats,
+            aovs,
+        })
+    }
+}
+
+// Keeps track of how much is left to read, so that a damaged checkpoint fails to load rather than
+// asking for more memory than there could be data for
+struct BoundedReader<'a, R> {
+    inner: &'a mut R,
+    remaining: u64,
+}
+
+impl<'a, R: Read> BoundedReader<'a, R> {
+    fn check_remaining(&self, count: usize, item_size: usize) -> Result<()> {
+        match count.checked_mul(item_size) {
+            Some(size) if size as u64 <= self.remaining => Ok(()),
+            _ => Err(anyhow!("Checkpoint is too short for {} items", count)),
+        }
+    }
+}
+
+impl<'a, R: Read> Read for BoundedReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.remaining = self.remaining.saturating_sub(read as u64);
+        Ok(read)
+    }
+}
+
+// Everything is stored little endian, with sizes as 64 bits whatever the platform
+fn write_u32(writer: &mut impl Write, value: u32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_usize(writer: &mut impl Write, value: usize) -> Result<()> {
+    write_u64(writer, value as u64)
+}
+
+fn write_bool(writer: &mut impl Write, value: bool) -> Result<()> {
+    writer.write_all(&[value as u8])?;
+    Ok(())
+}
+
+fn write_float(writer: &mut impl Write, value: FloatType) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_string(writer: &mut impl Write, value: &str) -> Result<()> {
+    write_usize(writer, value.len())?;
+    writer.write_all(value.as_bytes())?;
+    Ok(())
+}
+
+fn write_crop_window(writer: &mut impl Write, window: CropWindow) -> Result<()> {
+    write_usize(writer, window.x)?;
+    write_usize(writer, window.y)?;
+    write_usize(writer, window.width)?;
+    write_usize(writer, window.height)
+}
+
+fn write_aov_pixel(writer: &mut impl Write, pixel: &AovPixel) -> Result<()> {
+    let values = [
+        pixel.samples,
+        pixel.hits,
+        pixel.depth,
+        pixel.normal.x,
+        pixel.normal.y,
+        pixel.normal.z,
+        pixel.albedo.x,
+        pixel.albedo.y,
+        pixel.albedo.z,
+        pixel.uv.x,
+        pixel.uv.y,
+        pixel.time,
+    ];
+    for value in values.iter() {
+        write_float(writer, *value)?;
+    }
+    write_u32(writer, pixel.object_id)
+}
+
+fn write_option<W: Write, T>(
+    writer: &mut W,
+    value: Option<T>,
+    write_value: impl Fn(&mut W, T) -> Result<()>,
+) -> Result<()> {
+    write_bool(writer, value.is_some())?;
+    match value {
+        Some(value) => write_value(writer, value),
+        None => Ok(()),
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; size_of::<u32>()];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; size_of::<u64>()];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_usize(reader: &mut impl Read) -> Result<usize> {
+    let value = read_u64(reader)?;
+    if value > usize::MAX as u64 {
+        return Err(anyhow!("Size {} in checkpoint is too large", value));
+    }
+    Ok(value as usize)
+}
+
+fn read_bool(reader: &mut impl Read) -> Result<bool> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0] != 0)
+}
+
+fn read_float(reader: &mut impl Read) -> Result<FloatType> {
+    let mut bytes = [0; size_of::<FloatType>()];
+    reader.read_exact(&mut bytes)?;
+    Ok(FloatType::from_le_bytes(bytes))
+}
+
+fn read_string(reader: &mut BoundedReader<impl Read>) -> Result<String> {
+    let length = read_usize(reader)?;
+    if length > MAX_STRING_LENGTH {
+        return Err(anyhow!(
+            "String of {} bytes in checkpoint is too long",
+            length
+        ));
+    }
+    reader.check_remaining(length, 1)?;
+
+    let mut bytes = vec![0; length];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn read_crop_window(reader: &mut impl Read) -> Result<CropWindow> {
+    Ok(CropWindow::new(
+        read_usize(reader)?,
+        read_usize(reader)?,
+        read_usize(reader)?,
+        read_usize(reader)?,
+    ))
+}
+
+fn read_aov_pixel(reader: &mut impl Read) -> Result<AovPixel> {
+    Ok(AovPixel {
+        samples: read_float(reader)?,
+        hits: read_float(reader)?,
+        depth: read_float(reader)?,
+        normal: vec3(
+            read_float(reader)?,
+            read_float(reader)?,
+            read_float(reader)?,
+        ),
+        albedo: vec3(
+            read_float(reader)?,
+            read_float(reader)?,
+            read_float(reader)?,
+        ),
+        uv: cgmath::vec2(read_float(reader)?, read_float(reader)?),
+        time: read_float(reader)?,
+        object_id: read_u32(reader)?,
+    })
+}
+
+fn read_option<R: Read, T>(
+    reader: &mut R,
+    read_value: impl Fn(&mut R) -> Result<T>,
+) -> Result<Option<T>> {
+    if read_bool(reader)? {
+        read_value(reader).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let mut image = VectorImage::new(3, 2);
+        for (idx, pixel) in image.pixels_mut().enumerate() {
+            let value = idx as FloatType;
+            *pixel = cgmath::vec4(value * 0.1, value * 0.2, value * 0.3, value);
+        }
+
+        let mut aovs = AovImage::new(3, 2);
+        aovs.pixels_mut()[4] = AovPixel {
+            samples: 16.0,
+            hits: 12.0,
+            depth: 30.0,
+            normal: vec3(0.0, 12.0, 0.0),
+            albedo: vec3(6.0, 3.0, 1.5),
+            uv: cgmath::vec2(1.0, 2.0),
+            time: 8.0,
+            object_id: 7,
+        };
+
+        let checkpoint = RenderCheckpoint {
+            scene_name: "test".to_string(),
+            scene_hash: 0x1234_5678_9abc_def0,
+            settings: RenderSettings {
+                noise_threshold: Some(0.05),
+                max_passes: 100,
+                passes_per_round: Some(4),
+                outlier_sigma: Some(3.0),
+                seed: 42,
+                sampler: SamplerKind::Halton,
+                filter: PixelFilter {
+                    kind: FilterKind::Mitchell,
+                    radius: 1.5,
+                },
+                crop: Some(CropWindow::new(1, 0, 2, 2)),
+                aovs: true,
+                ..RenderSettings::new(4, 16)
+            },
+            round: 3,
+            passes_done: 16,
+            image,
+            sample_stats: (0..6)
+                .map(|idx| SampleStats {
+                    count: idx as FloatType,
+                    luminance: idx as FloatType * 0.25,
+                    luminance_squares: idx as FloatType * 0.5,
+                })
+                .collect(),
+            aovs: Some(aovs),
+        };
+
+        let mut bytes = Vec::new();
+        checkpoint.write_to(&mut bytes).unwrap();
+        let size = bytes.len() as u64;
+        let loaded = RenderCheckpoint::read_from(&mut bytes.as_slice(), size).unwrap();
+
+        assert_eq!(loaded.scene_name, checkpoint.scene_name);
+        assert_eq!(loaded.scene_hash, checkpoint.scene_hash);
+        assert_eq!(loaded.image_size(), (3, 2));
+        assert_eq!(loaded.round, 3);
+        assert_eq!(loaded.passes_done, 16);
+        assert_eq!(loaded.settings.noise_threshold, Some(0.05));
+        assert_eq!(loaded.settings.passes_per_round, Some(4));
+        assert_eq!(loaded.settings.max_sample_luminance, None);
+        assert_eq!(loaded.settings.outlier_sigma, Some(3.0));
+        assert_eq!(loaded.settings.seed, 42);
+        assert_eq!(loaded.settings.sampler, SamplerKind::Halton);
+        assert_eq!(loaded.settings.filter, checkpoint.settings.filter);
+        assert_eq!(loaded.settings.crop, checkpoint.settings.crop);
+        assert!(loaded
+            .image
+            .pixels()
+            .zip(checkpoint.image.pixels())
+            .all(|(a, b)| a == b));
+        assert_eq!(loaded.sample_stats, checkpoint.sample_stats);
+        assert!(loaded.settings.aovs);
+        assert_eq!(
+            loaded.aovs().unwrap().pixels(),
+            checkpoint.aovs().unwrap().pixels()
+        );
+
+        // Anything cut short should fail to load rather than come back with zeroes in, and so
+        // should anything claiming to be bigger than it is
+        assert!(RenderCheckpoint::read_from(&mut &bytes[..bytes.len() - 1], size - 1).is_err());
+        let mut huge = bytes.clone();
+        huge[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(RenderCheckpoint::read_from(&mut huge.as_slice(), size).is_err());
+    }
+}
@@ -6,6 +6,14 @@ use std::convert::{Infallible, TryFrom};
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Color(pub [FloatType; 4]);
 
+// How the values stored in an image relate to the light they stand for. Photos and other images
+// meant to be looked at are almost always sRGB, but data like normal maps is stored linearly.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
 impl Color {
     pub fn get_r(&self) -> FloatType {
         self.0[0]
@@ -20,6 +28,11 @@ impl Color {
         self.0[3]
     }
 
+    // Relative luminance, using the Rec. 709 primaries
+    pub fn luminance(&self) -> FloatType {
+        (0.2126 * self.0[0]) + (0.7152 * self.0[1]) + (0.0722 * self.0[2])
+    }
+
     #[must_use]
     pub fn gamma(self, power: FloatType) -> Self {
         Self([
@@ -30,6 +43,43 @@ impl Color {
         ])
     }
 
+    // The sRGB transfer function, which is a straight line near black and a 2.4 power curve
+    // everywhere else. Alpha is left alone.
+    #[must_use]
+    pub fn encode_srgb(self) -> Self {
+        self.map_rgb(|c| {
+            if c <= 0.003_130_8 {
+                c * 12.92
+            } else {
+                (1.055 * c.powf(1.0 / 2.4)) - 0.055
+            }
+        })
+    }
+
+    #[must_use]
+    pub fn decode_srgb(self) -> Self {
+        self.map_rgb(|c| {
+            if c <= 0.040_45 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        })
+    }
+
+    // Turn values from an image in the given color space into linear light
+    #[must_use]
+    pub fn decode(self, color_space: ColorSpace) -> Self {
+        match color_space {
+            ColorSpace::Srgb => self.decode_srgb(),
+            ColorSpace::Linear => self,
+        }
+    }
+
+    fn map_rgb(self, f: impl Fn(FloatType) -> FloatType) -> Self {
+        Self([f(self.0[0]), f(self.0[1]), f(self.0[2]), self.0[3]])
+    }
+
     #[must_use]
     pub fn attenuate(self, attenuation: FloatType) -> Self {
         Self([
@@ -111,6 +161,11 @@ impl From<Color> for cgmath::Vector3<FloatType> {
     }
 }
 
+// The luminance of a color that is still a plain vector, as colors are while being added up
+pub(crate) fn luminance(color: Vector3) -> FloatType {
+    Color::try_from(color).unwrap().luminance()
+}
+
 pub mod constants {
     use super::*;
 
@@ -130,3 +185,26 @@ pub mod constants {
     color_constant!(CYAN, 0.0, 1.0, 1.0);
     color_constant!(WHITE, 1.0, 1.0, 1.0);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_srgb_transfer() {
+        let linear = Color([0.0, 0.002, 0.214_041, 1.0]);
+        let encoded = linear.encode_srgb();
+        assert_eq!(encoded.get_r(), 0.0);
+        assert!((encoded.get_g() - 0.025_84).abs() < 0.0001);
+        assert!((encoded.get_b() - 0.5).abs() < 0.0001);
+        assert_eq!(encoded.get_a(), 1.0);
+
+        for value in [0.001, 0.01, 0.1, 0.5, 0.9, 1.0].iter() {
+            let color = Color([*value, *value, *value, 1.0]);
+            let round_trip = color.encode_srgb().decode_srgb();
+            assert!((round_trip.get_r() - value).abs() < 0.0001, "{}", value);
+        }
+
+        assert_eq!(linear.decode(ColorSpace::Linear), linear);
+    }
+}
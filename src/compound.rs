@@ -1,6 +1,6 @@
 use crate::{
     math::*, DefaultSkinnable, DefaultTransformable, GeometryHitResult, IntersectResultIteratorOps,
-    Intersectable, Ray, SkinnedHitResult, TimeDependentBounded,
+    Intersectable, Ray, Sampleable, Sampler, SkinnedHitResult, SurfaceSample, TimeDependentBounded,
 };
 use core::iter::FromIterator;
 use std::sync::Arc;
@@ -24,7 +24,9 @@ macro_rules! compound_primitive [
 ];
 
 // Start off by defining dynamic types for primitives and visible objects
-pub trait Primitive: Intersectable<Result = GeometryHitResult> + TimeDependentBounded {
+pub trait Primitive:
+    Intersectable<Result = GeometryHitResult> + TimeDependentBounded + Sampleable
+{
     fn to_dyn_primitive(self) -> DynPrimitive;
     fn decompose_box(self: Box<Self>) -> CompoundPrimitive;
     fn decompose(self) -> CompoundPrimitive;
@@ -34,6 +36,7 @@ impl<
         P: 'static
             + Intersectable<Result = GeometryHitResult>
             + TimeDependentBounded
+            + Sampleable
             + DefaultPrimitive,
     > Primitive for P
 {
@@ -51,7 +54,7 @@ impl<
 }
 
 pub trait DefaultPrimitive:
-    Intersectable<Result = GeometryHitResult> + TimeDependentBounded
+    Intersectable<Result = GeometryHitResult> + TimeDependentBounded + Sampleable
 {
 }
 
@@ -71,8 +74,9 @@ impl Intersectable for DynPrimitive {
         ray: &crate::Ray,
         t_min: crate::math::FloatType,
         t_max: crate::math::FloatType,
+        sampler: &mut dyn Sampler,
     ) -> Option<Self::Result> {
-        self.0.as_ref().intersect(ray, t_min, t_max)
+        self.0.as_ref().intersect(ray, t_min, t_max, sampler)
     }
 }
 
@@ -86,6 +90,16 @@ impl TimeDependentBounded for DynPrimitive {
     }
 }
 
+impl Sampleable for DynPrimitive {
+    fn sample_surface(&self, t: FloatType, sampler: &mut dyn Sampler) -> Option<SurfaceSample> {
+        self.0.as_ref().sample_surface(t, sampler)
+    }
+
+    fn surface_pdf(&self, point: Point3, surface_normal: Vector3, t: FloatType) -> FloatType {
+        self.0.as_ref().surface_pdf(point, surface_normal, t)
+    }
+}
+
 impl Primitive for DynPrimitive {
     fn to_dyn_primitive(self) -> DynPrimitive {
         self
@@ -119,8 +133,9 @@ impl Intersectable for SharedPrimitive {
         ray: &crate::Ray,
         t_min: crate::math::FloatType,
         t_max: crate::math::FloatType,
+        sampler: &mut dyn Sampler,
     ) -> Option<Self::Result> {
-        self.0.intersect(ray, t_min, t_max)
+        self.0.intersect(ray, t_min, t_max, sampler)
     }
 }
 
@@ -134,6 +149,16 @@ impl TimeDependentBounded for SharedPrimitive {
     }
 }
 
+impl Sampleable for SharedPrimitive {
+    fn sample_surface(&self, t: FloatType, sampler: &mut dyn Sampler) -> Option<SurfaceSample> {
+        self.0.sample_surface(t, sampler)
+    }
+
+    fn surface_pdf(&self, point: Point3, surface_normal: Vector3, t: FloatType) -> FloatType {
+        self.0.surface_pdf(point, surface_normal, t)
+    }
+}
+
 impl Primitive for SharedPrimitive {
     fn to_dyn_primitive(self) -> DynPrimitive {
         DynPrimitive::new(self)
@@ -151,13 +176,25 @@ impl Primitive for SharedPrimitive {
 impl DefaultTransformable for SharedPrimitive {}
 impl DefaultSkinnable for SharedPrimitive {}
 
-pub trait Visible: Intersectable<Result = SkinnedHitResult> + TimeDependentBounded {
+pub trait Visible:
+    Intersectable<Result = SkinnedHitResult> + TimeDependentBounded + Sampleable
+{
     fn to_dyn_visible(self) -> DynVisible;
     fn decompose_box(self: Box<Self>) -> CompoundVisible;
     fn decompose(self) -> CompoundVisible;
+    fn is_emissive(&self) -> bool;
+
+    // What the shape is made of, for telling scenes apart by
+    fn material_description(&self) -> String;
 }
 
-pub trait DefaultVisible: Intersectable<Result = SkinnedHitResult> + TimeDependentBounded {}
+pub trait DefaultVisible:
+    Intersectable<Result = SkinnedHitResult> + TimeDependentBounded + Sampleable
+{
+    fn material_description(&self) -> String {
+        String::new()
+    }
+}
 
 pub struct DynVisible(Box<dyn Visible>);
 
@@ -168,7 +205,11 @@ impl DynVisible {
 }
 
 impl<
-        P: 'static + Intersectable<Result = SkinnedHitResult> + TimeDependentBounded + DefaultVisible,
+        P: 'static
+            + Intersectable<Result = SkinnedHitResult>
+            + TimeDependentBounded
+            + Sampleable
+            + DefaultVisible,
     > Visible for P
 {
     fn to_dyn_visible(self) -> DynVisible {
@@ -182,13 +223,27 @@ impl<
     fn decompose(self) -> CompoundVisible {
         compound_visible![self]
     }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    fn material_description(&self) -> String {
+        DefaultVisible::material_description(self)
+    }
 }
 
 impl Intersectable for DynVisible {
     type Result = SkinnedHitResult;
 
-    fn intersect(&self, ray: &Ray, t_min: FloatType, t_max: FloatType) -> Option<Self::Result> {
-        self.0.as_ref().intersect(ray, t_min, t_max)
+    fn intersect(
+        &self,
+        ray: &Ray,
+        t_min: FloatType,
+        t_max: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Self::Result> {
+        self.0.as_ref().intersect(ray, t_min, t_max, sampler)
     }
 }
 
@@ -198,6 +253,16 @@ impl TimeDependentBounded for DynVisible {
     }
 }
 
+impl Sampleable for DynVisible {
+    fn sample_surface(&self, t: FloatType, sampler: &mut dyn Sampler) -> Option<SurfaceSample> {
+        self.0.as_ref().sample_surface(t, sampler)
+    }
+
+    fn surface_pdf(&self, point: Point3, surface_normal: Vector3, t: FloatType) -> FloatType {
+        self.0.as_ref().surface_pdf(point, surface_normal, t)
+    }
+}
+
 impl DefaultTransformable for DynVisible {}
 impl DefaultSkinnable for DynVisible {}
 
@@ -213,6 +278,14 @@ impl Visible for DynVisible {
     fn decompose(self) -> CompoundVisible {
         self.0.decompose_box()
     }
+
+    fn is_emissive(&self) -> bool {
+        self.0.as_ref().is_emissive()
+    }
+
+    fn material_description(&self) -> String {
+        self.0.as_ref().material_description()
+    }
 }
 
 // Now we need a compound of each of those
@@ -242,9 +315,15 @@ impl CompoundPrimitive {
 impl Intersectable for CompoundPrimitive {
     type Result = GeometryHitResult;
 
-    fn intersect(&self, ray: &Ray, t_min: FloatType, t_max: FloatType) -> Option<Self::Result> {
+    fn intersect(
+        &self,
+        ray: &Ray,
+        t_min: FloatType,
+        t_max: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Self::Result> {
         self.iter()
-            .filter_map(|i| i.intersect(ray, t_min, t_max))
+            .filter_map(|i| i.intersect(ray, t_min, t_max, sampler))
             .nearest()
     }
 }
@@ -257,6 +336,9 @@ impl TimeDependentBounded for CompoundPrimitive {
     }
 }
 
+// Compounds are flattened out by decompose before we ever need to sample them
+impl Sampleable for CompoundPrimitive {}
+
 // Question: Is the default implementation of transformable what we want here?
 impl DefaultTransformable for CompoundPrimitive {}
 impl DefaultSkinnable for CompoundPrimitive {}
@@ -327,9 +409,15 @@ impl CompoundVisible {
 impl Intersectable for CompoundVisible {
     type Result = SkinnedHitResult;
 
-    fn intersect(&self, ray: &Ray, t_min: FloatType, t_max: FloatType) -> Option<Self::Result> {
+    fn intersect(
+        &self,
+        ray: &Ray,
+        t_min: FloatType,
+        t_max: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Self::Result> {
         self.iter()
-            .filter_map(|i| i.intersect(ray, t_min, t_max))
+            .filter_map(|i| i.intersect(ray, t_min, t_max, sampler))
             .nearest()
     }
 }
@@ -342,6 +430,8 @@ impl TimeDependentBounded for CompoundVisible {
     }
 }
 
+impl Sampleable for CompoundVisible {}
+
 // Question: Is the default implementation of transformable what we want here?
 impl DefaultTransformable for CompoundVisible {}
 impl DefaultSkinnable for CompoundVisible {}
@@ -358,6 +448,17 @@ impl Visible for CompoundVisible {
     fn decompose(self) -> CompoundVisible {
         self.0.into_iter().flat_map(|f| f.decompose()).collect()
     }
+
+    fn is_emissive(&self) -> bool {
+        self.iter().any(Visible::is_emissive)
+    }
+
+    fn material_description(&self) -> String {
+        self.iter()
+            .map(Visible::material_description)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
 }
 
 impl IntoIterator for CompoundVisible {
@@ -0,0 +1,268 @@
+use crate::color::luminance;
+use crate::ray_scanner::SampleStats;
+use crate::{math::*, AovImage, RenderOutput, VectorImage};
+
+// A non-local means filter, guided by the AOVs. Each pixel becomes a weighted average of the
+// pixels around it, where the weight depends on how alike the patches around the two pixels are,
+// measured against how noisy they are, and on how alike their first hits are. Dividing by the
+// albedo first means that textures are put back afterwards rather than being blurred along with
+// the noise.
+#[derive(Debug, Clone, Copy)]
+pub struct Denoiser {
+    // Pixels up to this far away are averaged, with patches this far out from each of them
+    // compared to decide how much
+    pub radius: usize,
+    pub patch_radius: usize,
+
+    // How different two patches can be, in multiples of their noise, before they stop being
+    // averaged together. Bigger numbers give smoother images.
+    pub strength: FloatType,
+
+    // How different the normals, albedos and depths can be. Depths are compared relative to how
+    // far away the pixel is.
+    pub normal_sigma: FloatType,
+    pub albedo_sigma: FloatType,
+    pub depth_sigma: FloatType,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            radius: 6,
+            patch_radius: 1,
+            strength: 0.45,
+            normal_sigma: 0.3,
+            albedo_sigma: 0.1,
+            depth_sigma: 0.05,
+        }
+    }
+}
+
+// Surfaces darker than this are treated as this dark when taking the albedo out, so that black
+// surfaces don't blow up the noise
+const MIN_ALBEDO: FloatType = 0.01;
+
+#[derive(Debug, Clone, Copy)]
+struct Guide {
+    normal: Vector3,
+    albedo: Vector3,
+    depth: FloatType,
+}
+
+impl Denoiser {
+    // The result has as many samples in each pixel as the render did, so it can be written out
+    // in the same way
+    pub fn denoise(&self, output: &RenderOutput) -> VectorImage {
+        let image = &output.image;
+        let (width, height) = (image.width(), image.height());
+
+        let guides = match &output.aovs {
+            Some(aovs) => guides(aovs),
+            None => vec![None; width * height],
+        };
+        let albedos: Vec<_> = guides
+            .iter()
+            .map(|guide| match guide {
+                Some(guide) => guide.albedo.map(|c| c.max(MIN_ALBEDO)),
+                None => vec3(1.0, 1.0, 1.0),
+            })
+            .collect();
+
+        let means: Vec<_> = image
+            .pixels()
+            .map(|pixel| {
+                if pixel.w > 0.0 {
+                    pixel.truncate() / pixel.w
+                } else {
+                    Vector3::zero()
+                }
+            })
+            .collect();
+        let counts: Vec<_> = image.pixels().map(|pixel| pixel.w).collect();
+        let luminances: Vec<_> = means.iter().map(|mean| luminance(*mean)).collect();
+        let irradiances: Vec<_> = means
+            .iter()
+            .zip(albedos.iter())
+            .map(|(mean, albedo)| mean.div_element_wise(*albedo))
+            .collect();
+        let variances = variances(output, width, height);
+
+        let clamp = |x: isize, y: isize| {
+            let x = x.max(0).min(width as isize - 1) as usize;
+            let y = y.max(0).min(height as isize - 1) as usize;
+            (y * width) + x
+        };
+
+        let (radius, patch_radius) = (self.radius as isize, self.patch_radius as isize);
+        let patch_size = ((2 * patch_radius) + 1).pow(2) as FloatType;
+        let strength_squared = self.strength * self.strength;
+
+        let mut denoised = VectorImage::new(width, height);
+        for (x, y, dst) in denoised.enumerate_pixels_mut() {
+            let p = (y * width) + x;
+            let count = counts[p];
+            if count <= 0.0 {
+                continue;
+            }
+
+            let (x, y) = (x as isize, y as isize);
+            let mut sum = Vector3::zero();
+            let mut total_weight = 0.0;
+            for qy in (y - radius).max(0)..=(y + radius).min(height as isize - 1) {
+                for qx in (x - radius).max(0)..=(x + radius).min(width as isize - 1) {
+                    let q = clamp(qx, qy);
+                    if counts[q] <= 0.0 {
+                        continue;
+                    }
+
+                    let feature_weight = self.feature_weight(guides[p], guides[q]);
+                    if feature_weight <= 0.0 {
+                        continue;
+                    }
+
+                    let mut distance = 0.0;
+                    for oy in -patch_radius..=patch_radius {
+                        for ox in -patch_radius..=patch_radius {
+                            let (a, b) = (clamp(x + ox, y + oy), clamp(qx + ox, qy + oy));
+                            let (va, vb) = (variances[a], variances[b]);
+                            let difference = luminances[a] - luminances[b];
+                            distance += ((difference * difference) - (va + va.min(vb)))
+                                / (constants::EPSILON + (strength_squared * (va + vb)));
+                        }
+                    }
+
+                    let weight = (-(distance / patch_size).max(0.0)).exp() * feature_weight;
+                    sum += irradiances[q] * weight;
+                    total_weight += weight;
+                }
+            }
+
+            // The pixel itself always has a weight of one, so the total can't be zero
+            let mean = (sum / total_weight).mul_element_wise(albedos[p]);
+            *dst = mean.extend(1.0) * count;
+        }
+
+        denoised
+    }
+
+    fn feature_weight(&self, p: Option<Guide>, q: Option<Guide>) -> FloatType {
+        match (p, q) {
+            (Some(p), Some(q)) => {
+                let normal = (p.normal - q.normal).magnitude2() / (self.normal_sigma.powi(2));
+                let albedo = (p.albedo - q.albedo).magnitude2() / (self.albedo_sigma.powi(2));
+                let depth = ((p.depth - q.depth)
+                    / (self.depth_sigma * p.depth.max(constants::EPSILON)))
+                .powi(2);
+                (-(normal + albedo + depth)).exp()
+            }
+
+            // Something and nothing are never alike, but two pixels that both look out at the
+            // sky can only be told apart by their color
+            (None, None) => 1.0,
+            _ => 0.0,
+        }
+    }
+}
+
+// The variance of a single pixel is itself very noisy, so it is smoothed over its neighbours
+fn variances(output: &RenderOutput, width: usize, height: usize) -> Vec<FloatType> {
+    let raw: Vec<_> = output
+        .sample_stats
+        .iter()
+        .map(SampleStats::variance)
+        .collect();
+
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let (mut sum, mut count) = (0.0, 0.0);
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    if let Some(variance) = raw[(ny * width) + nx] {
+                        sum += variance;
+                        count += 1.0;
+                    }
+                }
+            }
+            if count > 0.0 {
+                sum / count
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+fn guides(aovs: &AovImage) -> Vec<Option<Guide>> {
+    aovs.pixels()
+        .iter()
+        .map(|pixel| {
+            if pixel.hits > 0.0 {
+                Some(Guide {
+                    normal: pixel.normal / pixel.hits,
+                    albedo: pixel.albedo / pixel.hits,
+                    depth: pixel.depth / pixel.hits,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::AovPixel;
+
+    #[test]
+    fn test_denoise() {
+        // A flat grey wall on the left and a flat dark one on the right, with a checkerboard of
+        // noise on top. Each pixel has four samples, spread out enough to explain the noise.
+        let (width, height) = (16, 8);
+        let mut image = VectorImage::new(width, height);
+        let mut aovs = AovImage::new(width, height);
+        let mut sample_stats = vec![SampleStats::default(); width * height].into_boxed_slice();
+        for (idx, (pixel, aov)) in image
+            .pixels_mut()
+            .zip(aovs.pixels_mut().iter_mut())
+            .enumerate()
+        {
+            let (x, y) = (idx % width, idx / width);
+            let base = if x < width / 2 { 0.5 } else { 0.1 };
+            let value = base * if (x + y) % 2 == 0 { 1.1 } else { 0.9 };
+            *pixel = cgmath::vec4(value, value, value, 1.0) * 4.0;
+            sample_stats[idx] = SampleStats {
+                count: 4.0,
+                luminance: 4.0 * value,
+                luminance_squares: 4.0 * (base * base) * (1.0 + (0.4 * 0.4)),
+            };
+            *aov = AovPixel {
+                samples: 4.0,
+                hits: 4.0,
+                depth: 40.0,
+                normal: vec3(0.0, 0.0, 4.0),
+                albedo: vec3(base, base, base) * 4.0,
+                ..AovPixel::default()
+            };
+        }
+
+        let output = RenderOutput {
+            image,
+            aovs: Some(aovs),
+            sample_stats,
+        };
+        let denoised = Denoiser::default().denoise(&output);
+
+        for (idx, pixel) in denoised.pixels().enumerate() {
+            let base = if idx % width < width / 2 { 0.5 } else { 0.1 };
+            assert_eq!(pixel.w, 4.0);
+            assert!(
+                ((pixel.x / pixel.w) - base).abs() < base * 0.05,
+                "{} {:?}",
+                idx,
+                pixel
+            );
+        }
+    }
+}
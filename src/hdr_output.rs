@@ -0,0 +1,292 @@
+use crate::{math::*, AovImage, Named, VectorImage};
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+impl Named for ExrPixelType {
+    const NAMES: &'static [(&'static str, Self)] =
+        &[("half", ExrPixelType::Half), ("float", ExrPixelType::Float)];
+}
+
+impl ExrPixelType {
+    // The numbers the file format uses for each type, and how many bytes they take up
+    fn code(self) -> i32 {
+        match self {
+            Self::Half => 1,
+            Self::Float => 2,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::Half => 2,
+            Self::Float => 4,
+        }
+    }
+}
+
+pub fn save_exr(
+    image: &VectorImage,
+    aovs: Option<&AovImage>,
+    path: impl AsRef<Path>,
+    pixel_type: ExrPixelType,
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_exr(image, aovs, &mut writer, pixel_type)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// A single part, uncompressed, scanline OpenEXR file with R, G and B channels. That is the
+// simplest thing that everything can read, and the files are no bigger than the render was. Any
+// AOVs go in the same file as layers, so depth ends up in a channel called "depth.Z".
+pub fn write_exr(
+    image: &VectorImage,
+    aovs: Option<&AovImage>,
+    writer: &mut impl Write,
+    pixel_type: ExrPixelType,
+) -> Result<()> {
+    let (width, height) = (image.width(), image.height());
+
+    let pixels: Vec<_> = image.averaged_pixels().collect();
+    let mut channels: Vec<(String, ExrPixelType, Vec<FloatType>)> = ["R", "G", "B"]
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            let values = pixels.iter().map(|pixel| pixel[idx]).collect();
+            (name.to_string(), pixel_type, values)
+        })
+        .collect();
+
+    for layer in aovs.iter().flat_map(|aovs| aovs.layers()) {
+        for (channel_name, values) in layer.channels {
+            // Halves can't hold every id exactly, so ids are always stored as full floats
+            let channel_type = if layer.name == "id" {
+                ExrPixelType::Float
+            } else {
+                pixel_type
+            };
+            let name = format!("{}.{}", layer.name, channel_name);
+            channels.push((name, channel_type, values));
+        }
+    }
+
+    writer.write_all(&20_000_630_i32.to_le_bytes())?;
+    writer.write_all(&2_i32.to_le_bytes())?;
+
+    // Channels have to be listed in alphabetical order, and the pixels for each row are stored
+    // a channel at a time in that order too
+    channels.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+    let mut channel_list = Vec::new();
+    for (name, channel_type, _) in channels.iter() {
+        channel_list.extend_from_slice(name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&channel_type.code().to_le_bytes());
+        channel_list.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        channel_list.extend_from_slice(&1_i32.to_le_bytes()); // x sampling
+        channel_list.extend_from_slice(&1_i32.to_le_bytes()); // y sampling
+    }
+    channel_list.push(0);
+
+    let mut window = Vec::new();
+    for value in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+
+    write_attribute(writer, "channels", "chlist", &channel_list)?;
+    write_attribute(writer, "compression", "compression", &[0])?;
+    write_attribute(writer, "dataWindow", "box2i", &window)?;
+    write_attribute(writer, "displayWindow", "box2i", &window)?;
+    write_attribute(writer, "lineOrder", "lineOrder", &[0])?;
+    write_attribute(writer, "pixelAspectRatio", "float", &1.0_f32.to_le_bytes())?;
+    write_attribute(writer, "screenWindowCenter", "v2f", &[0; 8])?;
+    write_attribute(writer, "screenWindowWidth", "float", &1.0_f32.to_le_bytes())?;
+    writer.write_all(&[0])?;
+
+    // Every row is its own block, and the header is followed by the offset of each of them from
+    // the start of the file
+    let header_size = 8
+        + attribute_size("channels", "chlist", channel_list.len())
+        + attribute_size("compression", "compression", 1)
+        + attribute_size("dataWindow", "box2i", window.len())
+        + attribute_size("displayWindow", "box2i", window.len())
+        + attribute_size("lineOrder", "lineOrder", 1)
+        + attribute_size("pixelAspectRatio", "float", 4)
+        + attribute_size("screenWindowCenter", "v2f", 8)
+        + attribute_size("screenWindowWidth", "float", 4)
+        + 1;
+    let row_data_size: usize = channels
+        .iter()
+        .map(|(_, channel_type, _)| width * channel_type.size())
+        .sum();
+    let first_row_offset = header_size + (height * 8);
+    for y in 0..height {
+        let offset = first_row_offset + (y * (8 + row_data_size));
+        writer.write_all(&(offset as u64).to_le_bytes())?;
+    }
+
+    for y in 0..height {
+        writer.write_all(&(y as i32).to_le_bytes())?;
+        writer.write_all(&(row_data_size as i32).to_le_bytes())?;
+
+        for (_, channel_type, values) in channels.iter() {
+            for value in values[(y * width)..((y + 1) * width)].iter().copied() {
+                match channel_type {
+                    ExrPixelType::Half => writer.write_all(&f32_to_half(value).to_le_bytes())?,
+                    ExrPixelType::Float => writer.write_all(&value.to_le_bytes())?,
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_attribute(
+    writer: &mut impl Write,
+    name: &str,
+    attribute_type: &str,
+    value: &[u8],
+) -> Result<()> {
+    writer.write_all(name.as_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(attribute_type.as_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(&(value.len() as i32).to_le_bytes())?;
+    writer.write_all(value)?;
+    Ok(())
+}
+
+fn attribute_size(name: &str, attribute_type: &str, value_size: usize) -> usize {
+    name.len() + 1 + attribute_type.len() + 1 + 4 + value_size
+}
+
+// Round a float to the nearest half float, with ties going to even. Anything too big for a half
+// becomes infinity, and anything too small becomes zero.
+fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinity, and NaN stays NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    let round = |value: u32, shift: u32| {
+        let truncated = value >> shift;
+        let remainder = value & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        if remainder > halfway || (remainder == halfway && (truncated & 1) == 1) {
+            truncated + 1
+        } else {
+            truncated
+        }
+    };
+
+    if half_exponent <= 0 {
+        // Too small for a normal half, so it has to be denormalised, with the leading one that
+        // the float leaves out put back in
+        if half_exponent < -10 {
+            return sign;
+        }
+        return sign | round(mantissa | 0x80_0000, (14 - half_exponent) as u32) as u16;
+    }
+
+    // Rounding up can carry into the exponent, which is what we want, even if it makes infinity
+    sign | round(((half_exponent as u32) << 23) | mantissa, 13) as u16
+}
+
+pub fn save_hdr(image: &VectorImage, path: impl AsRef<Path>) -> Result<()> {
+    let pixels: Vec<_> = image
+        .averaged_pixels()
+        .map(|pixel| image::Rgb([pixel.x, pixel.y, pixel.z]))
+        .collect();
+
+    let writer = BufWriter::new(File::create(path)?);
+    image::hdr::HDREncoder::new(writer).encode(&pixels, image.width(), image.height())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_f32_to_half() {
+        assert_eq!(f32_to_half(0.0), 0x0000);
+        assert_eq!(f32_to_half(-0.0), 0x8000);
+        assert_eq!(f32_to_half(1.0), 0x3c00);
+        assert_eq!(f32_to_half(-2.0), 0xc000);
+        assert_eq!(f32_to_half(0.5), 0x3800);
+        assert_eq!(f32_to_half(65504.0), 0x7bff);
+        assert_eq!(f32_to_half(1.0e6), 0x7c00);
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NAN) & 0x7c00, 0x7c00);
+        assert_ne!(f32_to_half(f32::NAN) & 0x3ff, 0);
+
+        // The smallest denormal, and something that rounds to it
+        assert_eq!(f32_to_half(2.0_f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_half(0.75 * 2.0_f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_half(2.0_f32.powi(-26)), 0x0000);
+
+        // Halfway between 1 and the next half goes to the even one, just above goes up
+        assert_eq!(f32_to_half(1.0 + 2.0_f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_half(1.0 + 3.0 * 2.0_f32.powi(-11)), 0x3c02);
+        assert_eq!(
+            f32_to_half(1.0 + 2.0_f32.powi(-11) + 2.0_f32.powi(-20)),
+            0x3c01
+        );
+    }
+
+    #[test]
+    fn test_exr_layout() {
+        let mut image = VectorImage::new(3, 2);
+        for pixel in image.pixels_mut() {
+            *pixel = cgmath::vec4(2.0, 4.0, 6.0, 2.0);
+        }
+
+        let mut bytes = Vec::new();
+        write_exr(&image, None, &mut bytes, ExrPixelType::Float).unwrap();
+
+        assert_eq!(&bytes[..4], &[0x76, 0x2f, 0x31, 0x01]);
+
+        // The offset table comes just before the rows, and its first entry points at row 0
+        let row_size = 8 + (3 * 3 * 4);
+        let mut offset_bytes = [0; 8];
+        offset_bytes.copy_from_slice(&bytes[bytes.len() - (2 * row_size) - 16..][..8]);
+        let first_row = u64::from_le_bytes(offset_bytes) as usize;
+        assert_eq!(first_row, bytes.len() - (2 * row_size));
+        assert_eq!(&bytes[first_row..first_row + 4], &0_i32.to_le_bytes());
+
+        // Blue comes first, and the pixels have been averaged
+        assert_eq!(
+            &bytes[first_row + 8..first_row + 12],
+            &3.0_f32.to_le_bytes()
+        );
+
+        // With AOVs there are another eleven channels, with the ids always as floats
+        let mut aov_bytes = Vec::new();
+        let aovs = AovImage::new(3, 2);
+        write_exr(&image, Some(&aovs), &mut aov_bytes, ExrPixelType::Half).unwrap();
+        let aov_row_size = 8 + (3 * 13 * 2) + (3 * 4);
+        offset_bytes.copy_from_slice(&aov_bytes[aov_bytes.len() - (2 * aov_row_size) - 16..][..8]);
+        assert_eq!(
+            u64::from_le_bytes(offset_bytes) as usize,
+            aov_bytes.len() - (2 * aov_row_size)
+        );
+    }
+}
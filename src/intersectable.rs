@@ -1,23 +1,41 @@
-use crate::{math::*, IntersectResult, IntersectResultIteratorOps, Ray};
+use crate::{math::*, IntersectResult, IntersectResultIteratorOps, Ray, Sampler};
 
 pub trait Intersectable: Send + Sync {
     type Result: IntersectResult;
 
-    fn intersect(&self, ray: &Ray, t_min: FloatType, t_max: FloatType) -> Option<Self::Result>;
+    fn intersect(
+        &self,
+        ray: &Ray,
+        t_min: FloatType,
+        t_max: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Self::Result>;
 }
 
 pub trait IntersectableIteratorOps {
     type Result: IntersectResult;
 
-    fn intersect(self, ray: &Ray, t_min: FloatType, t_max: FloatType) -> Option<Self::Result>;
+    fn intersect(
+        self,
+        ray: &Ray,
+        t_min: FloatType,
+        t_max: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Self::Result>;
 }
 
 impl<B: Intersectable, IntoIter: IntoIterator<Item = B>> IntersectableIteratorOps for IntoIter {
     type Result = B::Result;
 
-    fn intersect(self, ray: &Ray, t_min: FloatType, t_max: FloatType) -> Option<Self::Result> {
+    fn intersect(
+        self,
+        ray: &Ray,
+        t_min: FloatType,
+        t_max: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Self::Result> {
         self.into_iter()
-            .filter_map(|shape| shape.intersect(ray, t_min, t_max))
+            .filter_map(|shape| shape.intersect(ray, t_min, t_max, sampler))
             .nearest()
     }
 }
@@ -1,8 +1,8 @@
 use crate::{
     math::*, Bounded, BoundingBox, BoundingBoxIntersectionTester, CompoundPrimitive,
     CompoundVisible, DefaultSkinnable, DefaultTransformable, DynPrimitive, DynVisible,
-    GeometryHitResult, IntersectResultIteratorOps, Intersectable, Primitive, Ray,
-    TimeDependentBounded,
+    GeometryHitResult, IntersectResult, IntersectResultIteratorOps, Intersectable, Primitive, Ray,
+    Sampleable, Sampler, TimeDependentBounded,
 };
 use core::ops::Range;
 use std::mem::MaybeUninit;
@@ -197,6 +197,30 @@ impl<'a, P: TimeDependentBounded> KDTreeBlockIntersectIterator<'a, P> {
         self.work_stack_top += 1;
     }
 
+    // The range of items in the next leaf that the ray passes through
+    fn next_range(&mut self) -> Option<Range<usize>> {
+        while let Some(top) = self.pop_work_stack() {
+            // Check if the ray intersects the node
+            if top.bounding_box.intersect_with_tester(
+                &self.intersection_tester,
+                self.t_min,
+                self.t_max,
+            ) {
+                if let Some((left, right)) = top.children.as_deref() {
+                    // This is a branch node, so push its children
+                    self.push_work_stack(right);
+                    self.push_work_stack(left);
+                } else {
+                    // This is a leaf node, so return it
+                    return Some(top.range.clone());
+                }
+            }
+        }
+
+        // No intersects found
+        None
+    }
+
     fn pop_work_stack(&mut self) -> Option<&'a KDTreeEntry> {
         if self.work_stack_top > 0 {
             self.work_stack_top -= 1;
@@ -219,26 +243,8 @@ impl<'a, P: TimeDependentBounded> Iterator for KDTreeBlockIntersectIterator<'a,
     type Item = &'a [P];
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(top) = self.pop_work_stack() {
-            // Check if the ray intersects the node
-            if top.bounding_box.intersect_with_tester(
-                &self.intersection_tester,
-                self.t_min,
-                self.t_max,
-            ) {
-                if let Some((left, right)) = top.children.as_deref() {
-                    // This is a branch node, so push its children
-                    self.push_work_stack(right);
-                    self.push_work_stack(left);
-                } else {
-                    // This is a leaf node, so return it
-                    return Some(&self.items[top.range.clone()]);
-                }
-            }
-        }
-
-        // No intersects found
-        None
+        let items = self.items;
+        self.next_range().map(|range| &items[range])
     }
 }
 
@@ -291,6 +297,12 @@ impl<P: TimeDependentBounded> KDTree<P> {
     pub fn max_time(&self) -> FloatType {
         self.time_range.1
     }
+
+    // The items are sorted when the tree is built, but never move after that, so it is safe to
+    // hold on to indices into this
+    pub fn items(&self) -> &[P] {
+        self.items.as_ref()
+    }
 }
 
 // We do not implement TimeDependentBounded for KDTree. If you want a different time then you can always snapshot again
@@ -326,17 +338,49 @@ impl<P: TimeDependentBounded> KDTree<P> {
     }
 }
 
+impl<P: TimeDependentBounded + Intersectable> KDTree<P> {
+    // Like intersect, but also says which of the items was hit
+    pub fn intersect_item(
+        &self,
+        ray: &Ray,
+        t_min: FloatType,
+        t_max: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(usize, P::Result)> {
+        let mut blocks = self.intersecting_blocks(ray, t_min, t_max);
+        std::iter::from_fn(|| blocks.next_range())
+            .flatten()
+            .filter_map(|idx| {
+                self.items[idx]
+                    .intersect(ray, t_min, t_max, sampler)
+                    .map(|hit_result| (idx, hit_result))
+            })
+            .min_by(|(_, x), (_, y)| {
+                x.distance()
+                    .partial_cmp(&y.distance())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+    }
+}
+
 impl<P: TimeDependentBounded + Intersectable> Intersectable for KDTree<P> {
     type Result = P::Result;
 
-    fn intersect(&self, ray: &Ray, t_min: FloatType, t_max: FloatType) -> Option<Self::Result> {
+    fn intersect(
+        &self,
+        ray: &Ray,
+        t_min: FloatType,
+        t_max: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Self::Result> {
         self.intersecting_blocks(ray, t_min, t_max)
             .flat_map(|f| f.iter())
-            .filter_map(|i| i.intersect(ray, t_min, t_max))
+            .filter_map(|i| i.intersect(ray, t_min, t_max, sampler))
             .nearest()
     }
 }
 
+impl<P: TimeDependentBounded> Sampleable for KDTree<P> {}
 impl<P: TimeDependentBounded + Intersectable> DefaultTransformable for KDTree<P> {}
 impl<P: TimeDependentBounded + Intersectable> DefaultSkinnable for KDTree<P> {}
 
@@ -0,0 +1,264 @@
+use crate::math::*;
+use crate::{Color, Sampler};
+use anyhow::{anyhow, Result};
+use image::{GenericImageView, Pixel};
+use std::sync::Arc;
+
+// A camera described the way a real one is. Lengths on the camera side are in millimetres, and
+// the scene is taken to be in metres, which only matters for how big the aperture is. The sensor
+// size gives the field of view and the shape of the image, so it should match the image size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhysicalLens {
+    pub focal_length: FloatType,
+    pub f_number: FloatType,
+    pub sensor_width: FloatType,
+    pub sensor_height: FloatType,
+    pub shutter_time: FloatType,
+    pub iso: FloatType,
+}
+
+impl Default for PhysicalLens {
+    // A 50mm lens at f/2.8 on a full frame sensor
+    fn default() -> Self {
+        Self {
+            focal_length: 50.0,
+            f_number: 2.8,
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            shutter_time: 1.0 / 60.0,
+            iso: 100.0,
+        }
+    }
+}
+
+impl PhysicalLens {
+    pub fn fov(&self) -> Rad<FloatType> {
+        Rad(2.0 * (self.sensor_height / (2.0 * self.focal_length)).atan())
+    }
+
+    pub fn aspect_ratio(&self) -> FloatType {
+        self.sensor_width / self.sensor_height
+    }
+
+    // In scene units, so metres
+    pub fn aperture_diameter(&self) -> FloatType {
+        (self.focal_length / self.f_number) / 1000.0
+    }
+
+    // What the light reaching the sensor is multiplied by. The usual exposure value at ISO 100 is
+    // the log of the f-number squared over the shutter time, and the 1.2 makes a scene of average
+    // brightness come out at middle grey, so scenes need to be lit in real units for this to look
+    // right.
+    pub fn exposure(&self) -> FloatType {
+        let ev100_power = (self.f_number * self.f_number) / self.shutter_time * (100.0 / self.iso);
+        1.0 / (1.2 * ev100_power)
+    }
+}
+
+// A picture of the aperture, where brighter means more light gets through. Lens samples are
+// spread out in proportion to it, so bright spots come out in its shape.
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    total: FloatType,
+
+    // How much of the mask is in the rows up to each one, and then in the pixels up to each one
+    // along its row, both going from zero to one
+    rows: Vec<FloatType>,
+    columns: Vec<FloatType>,
+}
+
+impl ApertureMask {
+    pub fn new(
+        width: usize,
+        height: usize,
+        values: impl IntoIterator<Item = FloatType>,
+    ) -> Result<Self> {
+        let values: Vec<_> = values.into_iter().map(|value| value.max(0.0)).collect();
+        if width == 0 || values.len() != width * height {
+            return Err(anyhow!(
+                "The aperture mask should have {} by {} values",
+                width,
+                height
+            ));
+        }
+
+        let mut rows = vec![0.0; height + 1];
+        let mut columns = vec![0.0; (width + 1) * height];
+        for (y, row) in values.chunks(width).enumerate() {
+            let cumulative = &mut columns[(y * (width + 1))..((y + 1) * (width + 1))];
+            for (x, value) in row.iter().enumerate() {
+                cumulative[x + 1] = cumulative[x] + value;
+            }
+            rows[y + 1] = rows[y] + cumulative[width];
+            normalize(cumulative);
+        }
+
+        let total = rows[height];
+        if total <= 0.0 {
+            return Err(anyhow!("The aperture mask doesn't let any light through"));
+        }
+        normalize(&mut rows);
+
+        Ok(Self {
+            width,
+            height,
+            total,
+            rows,
+            columns,
+        })
+    }
+
+    pub fn from_image<Image: GenericImageView>(image: &Image) -> Result<Self> {
+        let (width, height) = image.dimensions();
+        let values = image
+            .pixels()
+            .map(|(_, _, pixel)| Color::from(pixel.to_rgb()).luminance());
+        Self::new(width as usize, height as usize, values)
+    }
+
+    // A point on the square from -1 to 1 that the mask covers, with the top of the mask at the
+    // top
+    fn sample(&self, u: Point2) -> Point2 {
+        let (y, y_fraction) = sample_cumulative(&self.rows, u.y);
+        let row = &self.columns[(y * (self.width + 1))..((y + 1) * (self.width + 1))];
+        let (x, x_fraction) = sample_cumulative(row, u.x);
+
+        let x = ((x as FloatType) + x_fraction) / (self.width as FloatType);
+        let y = ((y as FloatType) + y_fraction) / (self.height as FloatType);
+        point2((2.0 * x) - 1.0, 1.0 - (2.0 * y))
+    }
+}
+
+impl std::fmt::Debug for ApertureMask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApertureMask")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("total", &self.total)
+            .finish()
+    }
+}
+
+fn normalize(cumulative: &mut [FloatType]) {
+    let total = cumulative[cumulative.len() - 1];
+    if total > 0.0 {
+        cumulative.iter_mut().for_each(|value| *value /= total);
+    }
+}
+
+// Which bin a number falls in, and how far along it, for cumulative sums going from zero to one.
+// Empty bins are never picked.
+fn sample_cumulative(cumulative: &[FloatType], u: FloatType) -> (usize, FloatType) {
+    let bins = cumulative.len() - 1;
+    let idx = cumulative
+        .partition_point(|value| *value <= u)
+        .saturating_sub(1)
+        .min(bins - 1);
+    let width = cumulative[idx + 1] - cumulative[idx];
+    if width > 0.0 {
+        (idx, ((u - cumulative[idx]) / width).clamp(0.0, 1.0))
+    } else {
+        (idx, 0.5)
+    }
+}
+
+// The shape of the opening in the lens. A round aperture has no blades, otherwise it is a
+// polygon with a corner per blade, turned by the rotation. A mask replaces both.
+//
+// Cat's eye vignetting is the lens barrel cutting off part of the aperture for points away from
+// the middle of the image, which makes out of focus highlights there into lemon shapes and
+// darkens the corners. It is how far, in aperture radii, the barrel has moved by the corners.
+#[derive(Clone, Debug)]
+pub struct Aperture {
+    pub blades: usize,
+    pub rotation: Rad<FloatType>,
+    pub cats_eye: FloatType,
+    pub mask: Option<Arc<ApertureMask>>,
+}
+
+impl Default for Aperture {
+    fn default() -> Self {
+        Self {
+            blades: 0,
+            rotation: Rad(0.0),
+            cats_eye: 0.0,
+            mask: None,
+        }
+    }
+}
+
+impl Aperture {
+    // A point on the aperture, within the unit circle, or the square around it for masks
+    pub(crate) fn sample(&self, sampler: &mut dyn Sampler) -> Vector3 {
+        let point = match (&self.mask, self.blades) {
+            (Some(mask), _) => mask.sample(sampler.next_2d()).to_vec().extend(0.0),
+            (None, blades) if blades >= 3 => {
+                // Pick one of the triangles between the middle and the edges, then somewhere in
+                // it, going out with the square root so that the points are evenly spread
+                let u = sampler.next_2d();
+                let sector = u.x * (blades as FloatType);
+                let corner = (sector as usize).min(blades - 1);
+                let along = sector - (corner as FloatType);
+                let angle = |corner: usize| {
+                    (corner as FloatType) * 2.0 * constants::PI / (blades as FloatType)
+                };
+                let (a, b) = (angle(corner), angle(corner + 1));
+                let edge = vec3(a.cos(), a.sin(), 0.0).lerp(vec3(b.cos(), b.sin(), 0.0), along);
+                edge * u.y.sqrt()
+            }
+            _ => sampler.in_unit_disk(),
+        };
+
+        if self.rotation.0 != 0.0 {
+            let (sin, cos) = self.rotation.0.sin_cos();
+            vec3(
+                (point.x * cos) - (point.y * sin),
+                (point.x * sin) + (point.y * cos),
+                0.0,
+            )
+        } else {
+            point
+        }
+    }
+
+    // Whether the barrel lets a point on the aperture through, for a point on the image from
+    // -1 to 1 across its width and height
+    pub(crate) fn passes(&self, lens: Vector3, image_x: FloatType, image_y: FloatType) -> bool {
+        if self.cats_eye <= 0.0 {
+            return true;
+        }
+
+        // The corners are root two from the middle
+        let shift = self.cats_eye / (2.0 as FloatType).sqrt();
+        let barrel = vec3(image_x * shift, image_y * shift, 0.0);
+        (lens - barrel).magnitude2() <= 1.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_aperture_mask() {
+        // Only the top right of the mask lets light through, and only half as much in one of the
+        // two pixels that do
+        let mask = ApertureMask::new(4, 2, vec![0.0, 0.0, 1.0, 0.5, 0.0, 0.0, 0.0, 0.0]).unwrap();
+        for (u, (x, y)) in [
+            (point2(0.0, 0.0), (0.0, 1.0)),
+            (point2(0.5, 0.5), (0.375, 0.5)),
+            (point2(0.75, 0.5), (0.625, 0.5)),
+            (point2(0.99, 0.99), (0.985, 0.01)),
+        ]
+        .iter()
+        {
+            let point = mask.sample(*u);
+            assert!((point.x - x).abs() < 0.02, "{:?} {:?}", u, point);
+            assert!((point.y - y).abs() < 0.02, "{:?} {:?}", u, point);
+        }
+
+        assert!(ApertureMask::new(2, 2, vec![0.0; 4]).is_err());
+        assert!(ApertureMask::new(2, 2, vec![1.0; 3]).is_err());
+    }
+}
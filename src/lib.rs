@@ -1,58 +1,88 @@
 #![feature(maybe_uninit_extra, maybe_uninit_uninit_array)]
 #![feature(portable_simd)]
 
+mod aov;
 mod bounded;
 mod bounding_box;
 mod camera;
+mod cancellation;
+mod checkpoint;
 mod color;
 #[macro_use]
 mod compound;
-mod fixed_size_stack;
+mod denoise;
+mod hdr_output;
 mod hit_result;
 mod intersectable;
 mod kdtree;
+mod lens;
 mod materials;
+mod named;
 mod perlin;
+mod pixel_filter;
 mod ray;
 mod ray_scanner;
+mod render_settings;
+mod sampleable;
+mod samplers;
 mod scene;
 mod shapes;
+mod shutter;
 mod skinnable;
 mod sky;
 mod stats;
 mod textures;
+mod tile_scheduler;
+mod tone_mapping;
 mod transform;
 
 pub mod math;
 pub mod utils;
 
+pub use aov::{AovImage, AovLayer, AovPixel};
 pub use bounded::{
     Bounded, BoundedIteratorOps, TimeDependentBounded, TimeDependentBoundedIteratorOps,
 };
 pub use bounding_box::{BoundingBox, BoundingBoxIntersectionTester};
-pub use camera::{Camera, PreparedCamera};
-pub use color::Color;
+pub use camera::{Camera, CameraKeyframe, FisheyeMapping, PreparedCamera, StereoLayout, StereoRig};
+pub use cancellation::CancellationToken;
+pub use checkpoint::RenderCheckpoint;
+pub use color::{Color, ColorSpace};
 pub use compound::{
     CompoundPrimitive, CompoundVisible, DefaultPrimitive, DefaultVisible, DynPrimitive, DynVisible,
     Primitive, SharedPrimitive, Visible,
 };
+pub use denoise::Denoiser;
+pub use hdr_output::{save_exr, save_hdr, write_exr, ExrPixelType};
 pub use hit_result::{
     GeometryHitResult, IntersectResult, IntersectResultIteratorOps, SkinnedHitResult,
     WrappedIntersectResult,
 };
 pub use intersectable::{Intersectable, IntersectableIteratorOps};
 pub use kdtree::KDTree;
+pub use lens::{Aperture, ApertureMask, PhysicalLens};
 pub use materials::{BaseMaterial, Material, PartialScatterResult, ScatterResult, SurfaceMapper};
+pub use named::Named;
+pub use pixel_filter::{FilterKind, PixelFilter};
 pub use ray::Ray;
-pub use ray_scanner::scan;
+pub use ray_scanner::{
+    scan, scan_progressive, scan_resume, RenderOutput, RenderProgress, VectorImage,
+};
+pub use render_settings::{CropWindow, RenderSettings};
+pub use sampleable::{Sampleable, SurfaceSample};
+pub use samplers::{
+    HaltonSampler, RandomSampler, Sampler, SamplerKind, SobolSampler, StratifiedSampler,
+};
 pub use scene::Scene;
 pub use shapes::{MediumDensity, Sphere, TriangleVertex};
+pub use shutter::{Shutter, ShutterCurve};
 pub use skinnable::{DefaultSkinnable, Skinnable};
 pub use sky::Sky;
 pub use stats::{
     RenderStats, RenderStatsAccumulator, RenderStatsCollector, RenderStatsSource, TracingStats,
 };
 pub use textures::Texture;
+pub use tone_mapping::{ToneMapOperator, ToneMapper};
 pub use transform::{DefaultTransformable, Transformable};
 
 pub mod constants {
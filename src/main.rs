@@ -1,13 +1,13 @@
 extern crate clap;
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 
-use image::RgbImage;
+use image::{ImageBuffer, Rgb};
 
 use raster::{
-    compound_visible, prelude::*, Color, CompoundPrimitive, CompoundVisible, RenderStatsSource,
-    Skinnable, Texture, Transformable, TriangleVertex,
+    compound_visible, prelude::*, Color, ColorSpace, CompoundPrimitive, CompoundVisible, Named,
+    RenderStatsSource, Skinnable, Texture, Transformable, TriangleVertex,
 };
 
 use std::sync::{Arc, RwLock};
@@ -178,19 +178,19 @@ fn two_perlin_spheres(
 fn earth_map() -> impl Texture + Clone {
     let earth_bytes = include_bytes!("earthmap.jpg");
     let earth_image = image::load_from_memory(earth_bytes).unwrap();
-    image_texture(earth_image)
+    image_texture(earth_image, ColorSpace::Srgb)
 }
 
 fn brick_image() -> impl Texture + Clone {
     let brick_bytes = include_bytes!("brickwall.jpg");
     let brick_image = image::load_from_memory(brick_bytes).unwrap();
-    image_texture(brick_image)
+    image_texture(brick_image, ColorSpace::Srgb)
 }
 
 fn brick_normal_map() -> impl Texture + Clone {
     let brick_bytes = include_bytes!("brickwall_normal.jpg");
     let brick_image = image::load_from_memory(brick_bytes).unwrap();
-    image_texture(brick_image)
+    image_texture(brick_image, ColorSpace::Linear)
 }
 
 fn textured_earth(width: usize, height: usize) -> (raster::Camera, raster::Sky, CompoundVisible) {
@@ -670,6 +670,132 @@ fn mesh_cube(width: usize, height: usize) -> (raster::Camera, raster::Sky, Compo
         dist_to_focus,
     );
 
+    (camera, regular_sky(), mesh_cube_shapes())
+}
+
+// The same cube from above and to one side, with an orthographic camera so that parallel edges
+// stay parallel, as they would in a technical drawing
+fn mesh_cube_orthographic(
+    width: usize,
+    height: usize,
+) -> (raster::Camera, raster::Sky, CompoundVisible) {
+    let aspect_ratio = (width as FloatType) / (height as FloatType);
+    let lookfrom = Point3::new(20.0, 20.0, -20.0);
+    let lookat = Point3::new(-2.0, 1.0, 0.0);
+    let vup = vec3(0.0, 1.0, 0.0);
+    let view_height = 10.0;
+    let camera = raster::Camera::orthographic(
+        lookfrom,
+        lookat,
+        vup,
+        aspect_ratio * view_height,
+        view_height,
+    );
+
+    (camera, regular_sky(), mesh_cube_shapes())
+}
+
+// A panorama from beside the cube, covering every direction, as an environment probe would
+fn mesh_cube_panorama(
+    _width: usize,
+    _height: usize,
+) -> (raster::Camera, raster::Sky, CompoundVisible) {
+    let lookfrom = Point3::new(4.0, 1.5, -3.0);
+    let lookat = Point3::new(0.0, 1.0, 0.0);
+    let vup = vec3(0.0, 1.0, 0.0);
+    let camera = raster::Camera::equirectangular(lookfrom, lookat, vup);
+
+    (camera, regular_sky(), mesh_cube_shapes())
+}
+
+fn mesh_cube_fisheye(
+    width: usize,
+    height: usize,
+) -> (raster::Camera, raster::Sky, CompoundVisible) {
+    let aspect_ratio = (width as FloatType) / (height as FloatType);
+    let lookfrom = Point3::new(4.0, 1.5, -3.0);
+    let lookat = Point3::new(0.0, 1.0, 0.0);
+    let vup = vec3(0.0, 1.0, 0.0);
+    let camera = raster::Camera::fisheye(
+        lookfrom,
+        lookat,
+        vup,
+        Deg(180.0).into(),
+        aspect_ratio,
+        raster::FisheyeMapping::Equisolid,
+    );
+
+    (camera, regular_sky(), mesh_cube_shapes())
+}
+
+// Swings round the cube, zooming in as it goes. Each unit of time is one leg of the flight, so
+// rendering with shutters of 0,0.04 then 0.04,0.08 and so on gives 25 frames per leg.
+fn mesh_cube_flyby(width: usize, height: usize) -> (raster::Camera, raster::Sky, CompoundVisible) {
+    let aspect_ratio = (width as FloatType) / (height as FloatType);
+    let lookat = Point3::new(0.0, 1.0, 0.0);
+    let keyframe = |time, lookfrom: Point3, fov| {
+        let focus_dist = (lookfrom - lookat).magnitude();
+        raster::CameraKeyframe {
+            time,
+            lookfrom,
+            lookat,
+            vup: vec3(0.0, 1.0, 0.0),
+            fov: Deg(fov).into(),
+            aperture: 0.1,
+            focus_dist,
+        }
+    };
+    let camera = raster::Camera::animated(
+        vec![
+            keyframe(0.0, Point3::new(20.0, 5.0, -5.0), 20.0),
+            keyframe(1.0, Point3::new(14.0, 4.0, -14.0), 25.0),
+            keyframe(2.0, Point3::new(5.0, 3.0, -16.0), 30.0),
+            keyframe(3.0, Point3::new(-6.0, 2.0, -12.0), 35.0),
+        ],
+        aspect_ratio,
+    )
+    .unwrap();
+
+    (camera, regular_sky(), mesh_cube_shapes())
+}
+
+// A close up of the top of the cube with a long, fast lens, so that the small lights far behind
+// it come out as six sided bokeh, and lemon shaped towards the edges
+fn mesh_cube_bokeh(width: usize, height: usize) -> (raster::Camera, raster::Sky, CompoundVisible) {
+    let aspect_ratio = (width as FloatType) / (height as FloatType);
+    let lookfrom = Point3::new(8.0, 2.4, -4.0);
+    let lookat = Point3::new(0.0, 2.2, 0.0);
+    let vup = vec3(0.0, 1.0, 0.0);
+    let lens = raster::PhysicalLens {
+        focal_length: 200.0,
+        f_number: 2.0,
+        sensor_width: 24.0 * aspect_ratio,
+        sensor_height: 24.0,
+        shutter_time: 0.5,
+        iso: 640.0,
+    };
+    let focus_dist = (lookfrom - lookat).magnitude() - 1.0;
+    let camera = raster::Camera::physical(lookfrom, lookat, vup, &lens, focus_dist).with_aperture(
+        raster::Aperture {
+            blades: 6,
+            rotation: Deg(15.0).into(),
+            cats_eye: 0.5,
+            ..raster::Aperture::default()
+        },
+    );
+
+    let mut shapes = mesh_cube_shapes();
+    for idx in 0..12 {
+        let offset = idx as FloatType;
+        let position = point3(-25.0, 2.0 + (offset * 0.9) % 3.0, 8.5 + (offset * 0.7));
+        let color = Color([50.0, 35.0 + (offset % 3.0) * 7.5, 20.0, 1.0]);
+        shapes.push(sphere(position, 0.03).apply_material(diffuse_light(solid_texture(color))));
+    }
+
+    (camera, regular_sky(), shapes)
+}
+
+fn mesh_cube_shapes() -> CompoundVisible {
     let points = [
         (point3(-1.0, 0.0, 1.0), point2(0.0, 0.0)),
         (point3(1.0, 0.0, 1.0), point2(0.0, 0.0)),
@@ -696,7 +822,7 @@ fn mesh_cube(width: usize, height: usize) -> (raster::Camera, raster::Sky, Compo
 
     let floor = solid_texture(Color([0.2, 0.25, 0.2, 0.0]));
 
-    let shapes = compound_visible![
+    compound_visible![
         sphere(Point3::new(0.0, -1000.0, 0.0), 1000.0).apply_material(lambertian(floor),),
         sphere(point3(-5.0, 2.0, -1.0), 2.0)
             .apply_material(metal(Color([1.0, 1.0, 1.0, 0.0]), 0.0)),
@@ -709,9 +835,7 @@ fn mesh_cube(width: usize, height: usize) -> (raster::Camera, raster::Sky, Compo
         )
         .unwrap()
         .apply_material(metal(Color([0.8, 0.8, 1.0, 1.0]), 0.001)),
-    ];
-
-    (camera, regular_sky(), shapes)
+    ]
 }
 
 fn teapot(width: usize, height: usize) -> (raster::Camera, raster::Sky, CompoundVisible) {
@@ -760,14 +884,32 @@ fn teapot(width: usize, height: usize) -> (raster::Camera, raster::Sky, Compound
 const DEFAULT_WIDTH: usize = 1920;
 const DEFAULT_HEIGHT: usize = 1080;
 const DEFAULT_MIN_PASSES: usize = 100;
+const DEFAULT_MAX_PASSES: usize = 1000;
 const DEFAULT_THREADS: usize = 8;
 const DEFAULT_ENABLE_SPATIAL_PARTITIONING: bool = true;
+const DEFAULT_MAX_DEPTH: usize = 50;
+const DEFAULT_MIN_DEPTH: usize = 3;
+const DEFAULT_RUSSIAN_ROULETTE: bool = true;
+const DEFAULT_SEED: u64 = 0;
+const DEFAULT_SAMPLER: &str = "sobol";
+const DEFAULT_FILTER: &str = "box";
+const DEFAULT_INTEROCULAR: FloatType = 0.065;
+const DEFAULT_SHUTTER_CURVE: &str = "box";
+const DEFAULT_CHECKPOINT_SECONDS: f64 = 300.0;
+const DEFAULT_EXR_TYPE: &str = "half";
+const DEFAULT_EXPOSURE: FloatType = 0.0;
+const DEFAULT_TONEMAP: &str = "clamp";
+const DEFAULT_BIT_DEPTH: usize = 8;
+
+// Scene fingerprints only go by the bounds of the shapes, not their exact geometry
+const SCENE_COMPARISON: &str =
+    "Scenes are compared by their cameras, skies and materials, and only by the bounds of their shapes";
 
 type SceneResult = (raster::Camera, raster::Sky, CompoundVisible);
 type SceneFactory = fn(usize, usize) -> SceneResult;
 type BuiltinScene = (&'static str, SceneFactory);
 
-const BUILTIN_SCENES: [BuiltinScene; 16] = [
+const BUILTIN_SCENES: [BuiltinScene; 21] = [
     ("random", random_scene),
     ("mine", my_test_scene),
     ("twospheres", two_spheres),
@@ -783,6 +925,11 @@ const BUILTIN_SCENES: [BuiltinScene; 16] = [
     ("concave_mirror", concave_mirror),
     ("convex_mirror", convex_mirror),
     ("mesh_cube", mesh_cube),
+    ("mesh_cube_orthographic", mesh_cube_orthographic),
+    ("mesh_cube_panorama", mesh_cube_panorama),
+    ("mesh_cube_fisheye", mesh_cube_fisheye),
+    ("mesh_cube_flyby", mesh_cube_flyby),
+    ("mesh_cube_bokeh", mesh_cube_bokeh),
     ("teapot", teapot),
 ];
 
@@ -791,6 +938,7 @@ fn command_line() -> clap::ArgMatches<'static> {
         .version("1.0")
         .author("Stewart Tootill <stewart.tootill@live.co.uk>")
         .about("My raytracer")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("width")
                 .short("w")
@@ -833,6 +981,146 @@ fn command_line() -> clap::ArgMatches<'static> {
                 ))
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("noise-threshold")
+                .long("noise-threshold")
+                .help("Keep sampling pixels until their relative error is below this, off by default")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-passes")
+                .long("max-passes")
+                .help(&format!(
+                    "Maximum number of passes per pixel when using a noise threshold, defaults to {}",
+                    DEFAULT_MAX_PASSES
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-seconds")
+                .long("max-seconds")
+                .help("Stop rendering after this many seconds and write out what we have")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("passes-per-round")
+                .long("passes-per-round")
+                .help(
+                    "Number of passes between progress updates, defaults to one per thread",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("preview")
+                .long("preview")
+                .help("File to write the image so far to after every round of passes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .help("File to save the render to, so that it can be resumed")
+                .long_help("File to save the render to, so that it can be resumed. It is saved every --checkpoint-seconds and again when the render finishes.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("checkpoint-seconds")
+                .long("checkpoint-seconds")
+                .help(&format!(
+                    "Minimum number of seconds between checkpoints, defaults to {}",
+                    DEFAULT_CHECKPOINT_SECONDS
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .help("Checkpoint file to carry on rendering from")
+                .long_help("Checkpoint file to carry on rendering from. The scene, size and settings all come from the checkpoint, although the pass counts can be raised. Stereo, aperture and shutter options have to be given again.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-depth")
+                .long("max-depth")
+                .help(&format!(
+                    "Maximum number of bounces per path, defaults to {}",
+                    DEFAULT_MAX_DEPTH
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("min-depth")
+                .long("min-depth")
+                .help(&format!(
+                    "Number of bounces before russian roulette can end a path, defaults to {}",
+                    DEFAULT_MIN_DEPTH
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("russian-roulette")
+                .long("russian-roulette")
+                .possible_values(&["true", "false"])
+                .help(&format!(
+                    "Enable russian roulette path termination, defaults to {}",
+                    DEFAULT_RUSSIAN_ROULETTE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-sample-luminance")
+                .long("max-sample-luminance")
+                .help("Clamp the brightness of every sample to this, off by default")
+                .long_help("Clamp the brightness of every sample to this, apart from light the camera sees directly, to get rid of fireflies. Brightness is measured after the camera's exposure and before --exposure. Off by default.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("outlier-sigma")
+                .long("outlier-sigma")
+                .help("Standard deviations above which bright samples are brought down, off by default")
+                .long_help("Bring samples that are more than this many standard deviations brighter than the rest of their pixel back down, to get rid of fireflies. Like --max-sample-luminance, this looks at samples after the camera's exposure. Off by default.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .help(&format!(
+                    "Seed for all of the random numbers, defaults to {}",
+                    DEFAULT_SEED
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sampler")
+                .long("sampler")
+                .possible_values(
+                    &raster::SamplerKind::names(),
+                )
+                .help(&format!(
+                    "Choose how samples are spread out, defaults to {}",
+                    DEFAULT_SAMPLER
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .possible_values(
+                    &raster::FilterKind::names(),
+                )
+                .help(&format!(
+                    "Choose how samples are spread over the pixels around them, defaults to {}",
+                    DEFAULT_FILTER
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("filter-radius")
+                .long("filter-radius")
+                .help("Radius of the filter in pixels, defaults to one that suits the filter")
+                .long_help("Radius of the filter in pixels. Defaults to 0.5 for box, 1 for tent, 1.5 for gaussian and 2 for mitchell and lanczos.")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("enable-spatial-partitioning")
                 .long("enable-spatial-partitioning")
@@ -843,20 +1131,529 @@ fn command_line() -> clap::ArgMatches<'static> {
                 ))
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("exr-type")
+                .long("exr-type")
+                .global(true)
+                .possible_values(
+                    &raster::ExrPixelType::names(),
+                )
+                .help(&format!(
+                    "Type of the pixels in .exr output, defaults to {}",
+                    DEFAULT_EXR_TYPE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("exposure")
+                .long("exposure")
+                .global(true)
+                .allow_hyphen_values(true)
+                .help(&format!(
+                    "Exposure adjustment in stops before tone mapping, defaults to {}",
+                    DEFAULT_EXPOSURE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tonemap")
+                .long("tonemap")
+                .global(true)
+                .possible_values(
+                    &raster::ToneMapOperator::names(),
+                )
+                .help(&format!(
+                    "How to bring bright values into range for 8 and 16 bit output, defaults to {}",
+                    DEFAULT_TONEMAP
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("white-point")
+                .long("white-point")
+                .global(true)
+                .help("Brightness that maps to white for the reinhard-extended and hable tone maps")
+                .long_help("Brightness that maps to white for the reinhard-extended and hable tone maps. Defaults to the brightest pixel for reinhard-extended and 11.2 for hable.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("bit-depth")
+                .long("bit-depth")
+                .global(true)
+                .possible_values(&["8", "16"])
+                .help(&format!(
+                    "Bits per channel for images that aren't .exr or .hdr, defaults to {}",
+                    DEFAULT_BIT_DEPTH
+                ))
+                .long_help(&format!(
+                    "Bits per channel for images that aren't .exr or .hdr, defaults to {}. Only some formats, such as .png, can be 16 bit.",
+                    DEFAULT_BIT_DEPTH
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("aovs")
+                .long("aovs")
+                .global(true)
+                .help("Also write depth, normal, albedo, uv, time and object id layers")
+                .long_help("Also write depth, normal, albedo, uv, time and object id layers for the first thing each camera ray hits. They go in the same file for .exr, and next to the output as <name>.<layer>.<ext> for anything else."),
+        )
+        .arg(
+            Arg::with_name("crop")
+                .long("crop")
+                .conflicts_with("crop-normalized")
+                .help("Only render part of the image, given as x,y,width,height in pixels from the top left.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("crop-normalized")
+                .long("crop-normalized")
+                .help("Only render part of the image, given as x,y,width,height in fractions of the image size.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shutter")
+                .long("shutter")
+                .help("Times the shutter opens and closes, as open,close, defaults to 0,1")
+                .long_help("Times the shutter opens and closes, as open,close, which defaults to 0,1. Things that move over that time are blurred, and animated cameras are rendered as they are during it, so a sequence of frames can be rendered with a sequence of shutter times. Resuming needs the same shutter again.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shutter-curve")
+                .long("shutter-curve")
+                .possible_values(
+                    &raster::ShutterCurve::names(),
+                )
+                .help(&format!(
+                    "How open the shutter is while it opens and closes, defaults to {}",
+                    DEFAULT_SHUTTER_CURVE
+                ))
+                .long_help(&format!(
+                    "How open the shutter is while it opens and closes, which changes how motion blur fades out, defaults to {}",
+                    DEFAULT_SHUTTER_CURVE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shutter-ramp")
+                .long("shutter-ramp")
+                .help("Fraction of the time a trapezoid shutter spends opening, defaults to 0.25")
+                .long_help("Fraction of the time a trapezoid shutter spends opening, and again closing, up to 0.5. Defaults to 0.25.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rolling-shutter")
+                .long("rolling-shutter")
+                .help("Fraction of the shutter time spent reading out the rows, off by default")
+                .long_help("Fraction of the shutter time spent reading the image out from top to bottom, so that lower rows are exposed later. Off by default.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stereo")
+                .long("stereo")
+                .possible_values(
+                    &raster::StereoLayout::names(),
+                )
+                .help("Render the left and right eyes into the two halves of the image")
+                .long_help("Render the left and right eyes into the two halves of the image. Panoramic scenes are rendered in omni-directional stereo.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("interocular")
+                .long("interocular")
+                .requires("stereo")
+                .help(&format!(
+                    "Distance between the eyes of a stereo render, in scene units, defaults to {}",
+                    DEFAULT_INTEROCULAR
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("convergence")
+                .long("convergence")
+                .requires("stereo")
+                .help("Distance at which the eyes of a stereo render meet, parallel by default")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("aperture-blades")
+                .long("aperture-blades")
+                .help("Number of sides of a polygonal aperture, round by default")
+                .long_help("Make the aperture a polygon with this many sides, rather than round, which shapes the bokeh. Replaces the aperture the scene has, and only changes cameras that have depth of field.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("aperture-rotation")
+                .long("aperture-rotation")
+                .allow_hyphen_values(true)
+                .help("Turn the aperture by this many degrees")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cats-eye")
+                .long("cats-eye")
+                .help("How far the lens barrel cuts into the aperture, in aperture radii")
+                .long_help("How far the lens barrel cuts into the aperture towards the corners of the image, in aperture radii, which makes bokeh there lemon shaped")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("aperture-mask")
+                .long("aperture-mask")
+                .help("Image of the aperture, used instead of the blades")
+                .long_help("Image of the aperture, where brighter lets more light through, to give bokeh its shape. Used instead of the blades.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("full-frame")
+                .long("full-frame")
+                .global(true)
+                .help("Write the whole image when it has been cropped, rather than just the crop")
+                .long_help("Write the whole image when it has been cropped, with everything outside the crop left black, rather than just the crop."),
+        )
+        .arg(
+            Arg::with_name("denoise")
+                .long("denoise")
+                .global(true)
+                .help("Denoise the image before writing it")
+                .long_help("Denoise the image before writing it, guided by the albedo and normal of the first thing each camera ray hits. Previews aren't denoised."),
+        )
         .arg(
             Arg::with_name("output")
                 .help("File to write to")
+                .long_help("File to write to. Images with .exr or .hdr extensions are written in linear floating point, anything else is tone mapped.")
                 .required(true)
                 .index(1)
                 .takes_value(true),
         )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("Add up checkpoints of the same scene, rendered separately, into one image")
+                .arg(
+                    Arg::with_name("output")
+                        .help("File to write to")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("inputs")
+                        .help("Checkpoint files to merge")
+                        .required(true)
+                        .multiple(true)
+                        .index(2)
+                        .takes_value(true),
+                ),
+        )
         .get_matches()
 }
 
+fn to_ldr_image<T: image::Primitive + 'static>(
+    vector_image: &raster::VectorImage,
+    tone_mapper: &raster::ToneMapper,
+) -> ImageBuffer<Rgb<T>, Vec<T>> {
+    let mut surf = ImageBuffer::new(vector_image.width() as u32, vector_image.height() as u32);
+
+    tone_mapper
+        .apply(vector_image)
+        .into_iter()
+        .zip(surf.pixels_mut())
+        .fold((), |_, (color, dst)| {
+            *dst = color.encode_srgb().into();
+        });
+
+    surf
+}
+
+// How images are written out, which is the same whether they come from a render or a merge
+struct OutputSettings {
+    exr_type: raster::ExrPixelType,
+    tone_mapper: raster::ToneMapper,
+    bit_depth: usize,
+    aovs: bool,
+    denoiser: Option<raster::Denoiser>,
+    full_frame: bool,
+}
+
+fn output_settings(matches: &clap::ArgMatches) -> OutputSettings {
+    let exr_type =
+        raster::ExrPixelType::from_name(matches.value_of("exr-type").unwrap_or(DEFAULT_EXR_TYPE))
+            .unwrap();
+    let exposure = matches
+        .value_of("exposure")
+        .and_then(|v| v.parse::<FloatType>().ok())
+        .unwrap_or(DEFAULT_EXPOSURE);
+    let operator =
+        raster::ToneMapOperator::from_name(matches.value_of("tonemap").unwrap_or(DEFAULT_TONEMAP))
+            .unwrap();
+    let white_point = matches
+        .value_of("white-point")
+        .and_then(|v| v.parse::<FloatType>().ok());
+    let bit_depth = matches
+        .value_of("bit-depth")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_BIT_DEPTH);
+
+    OutputSettings {
+        exr_type,
+        tone_mapper: raster::ToneMapper {
+            exposure,
+            operator,
+            white_point,
+        },
+        bit_depth,
+        aovs: matches.is_present("aovs"),
+        full_frame: matches.is_present("full-frame"),
+        denoiser: if matches.is_present("denoise") {
+            Some(raster::Denoiser::default())
+        } else {
+            None
+        },
+    }
+}
+
+// Puts the channels of a layer into red, green and blue. A single channel goes into all three,
+// and a layer with two leaves blue empty.
+fn aov_layer_pixels(layer: &raster::AovLayer) -> Vec<Vector3> {
+    let channel = |idx: usize| &layer.channels[idx.min(layer.channels.len() - 1)].1;
+    (0..channel(0).len())
+        .map(|i| match layer.channels.len() {
+            2 => vec3(channel(0)[i], channel(1)[i], 0.0),
+            _ => vec3(channel(0)[i], channel(1)[i], channel(2)[i]),
+        })
+        .collect()
+}
+
+fn aov_hdr_image(layer: &raster::AovLayer, width: usize, height: usize) -> raster::VectorImage {
+    let mut image = raster::VectorImage::new(width, height);
+    for (dst, pixel) in image.pixels_mut().zip(aov_layer_pixels(layer)) {
+        *dst = pixel.extend(1.0);
+    }
+    image
+}
+
+// Layers that aren't colors have to be squeezed into something that can be looked at. Depth is
+// scaled so the furthest hit is white, normals go from -1..1 to 0..1, and every object id gets
+// its own made up color.
+fn aov_ldr_image<T: image::Primitive + 'static>(
+    layer: &raster::AovLayer,
+    width: usize,
+    height: usize,
+) -> ImageBuffer<Rgb<T>, Vec<T>> {
+    let pixels = aov_layer_pixels(layer);
+    let max_depth = pixels.iter().map(|pixel| pixel.x).fold(0.0, FloatType::max);
+
+    let mut surf = ImageBuffer::new(width as u32, height as u32);
+    for (pixel, dst) in pixels.into_iter().zip(surf.pixels_mut()) {
+        let color = match layer.name {
+            "depth" if max_depth > 0.0 => pixel / max_depth,
+            "normal" => (pixel * 0.5) + vec3(0.5, 0.5, 0.5),
+            "id" => {
+                let hash = (pixel.x as u32).wrapping_mul(0x9e37_79b9);
+                if pixel.x > 0.0 {
+                    vec3(hash >> 24, hash >> 16, hash >> 8).map(|c| (c & 0xff) as FloatType / 255.0)
+                } else {
+                    Vector3::zero()
+                }
+            }
+            _ => pixel,
+        };
+
+        let color = Color::try_from(color).unwrap();
+        *dst = if layer.name == "albedo" {
+            color.encode_srgb().into()
+        } else {
+            color.into()
+        };
+    }
+
+    surf
+}
+
+// The extension picks the format. OpenEXR and Radiance files keep the linear values from the
+// render, and everything else is tone mapped and goes through the image crate as sRGB. Only
+// OpenEXR can hold more than one image, so for anything else each AOV layer gets its own file.
+fn save_image(
+    vector_image: &raster::VectorImage,
+    aovs: Option<&raster::AovImage>,
+    file: &str,
+    output: &OutputSettings,
+) -> anyhow::Result<()> {
+    let path = std::path::Path::new(file);
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("exr") => return raster::save_exr(vector_image, aovs, file, output.exr_type),
+        Some("hdr") => raster::save_hdr(vector_image, file)?,
+        _ if output.bit_depth == 16 => {
+            to_ldr_image::<u16>(vector_image, &output.tone_mapper).save(file)?
+        }
+        _ => to_ldr_image::<u8>(vector_image, &output.tone_mapper).save(file)?,
+    }
+
+    let (width, height) = (vector_image.width(), vector_image.height());
+    for layer in aovs.iter().flat_map(|aovs| aovs.layers()) {
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("");
+        let layer_file = path.with_file_name(match path.extension() {
+            Some(extension) => format!("{}.{}.{}", stem, layer.name, extension.to_string_lossy()),
+            None => format!("{}.{}", stem, layer.name),
+        });
+
+        match extension.as_deref() {
+            Some("hdr") => raster::save_hdr(&aov_hdr_image(&layer, width, height), layer_file)?,
+            _ if output.bit_depth == 16 => {
+                aov_ldr_image::<u16>(&layer, width, height).save(layer_file)?
+            }
+            _ => aov_ldr_image::<u8>(&layer, width, height).save(layer_file)?,
+        }
+    }
+
+    Ok(())
+}
+
+// The denoiser needs the AOVs, so a render can have them without them having been asked for
+fn save_output(
+    render_output: &raster::RenderOutput,
+    crop: Option<raster::CropWindow>,
+    file: &str,
+    output: &OutputSettings,
+) -> anyhow::Result<()> {
+    let cropped;
+    let render_output = match crop {
+        Some(crop) if !output.full_frame => {
+            cropped = render_output.crop(crop);
+            &cropped
+        }
+        _ => render_output,
+    };
+
+    let aovs = render_output.aovs.as_ref().filter(|_| output.aovs);
+    match &output.denoiser {
+        Some(denoiser) => save_image(&denoiser.denoise(render_output), aovs, file, output),
+        None => save_image(&render_output.image, aovs, file, output),
+    }
+}
+
+// Four numbers separated by commas, which is how crop windows are given
+fn parse_crop<T: std::str::FromStr>(value: &str) -> Option<(T, T, T, T)> {
+    let mut values = value.split(',').map(|v| v.trim().parse::<T>().ok());
+    let crop = (
+        values.next()??,
+        values.next()??,
+        values.next()??,
+        values.next()??,
+    );
+    match values.next() {
+        Some(_) => None,
+        None => Some(crop),
+    }
+}
+
+fn load_checkpoint(path: &str) -> raster::RenderCheckpoint {
+    raster::RenderCheckpoint::load(path).unwrap_or_else(|err| {
+        println!("Failed to read checkpoint {}: {}", path, err);
+        std::process::exit(1);
+    })
+}
+
+// Renders of the same scene with different seeds are independent, so adding their sums
+// together gives the same image as one render with all of their samples. Crops of different
+// parts of the image can be merged too, although then they can only be written as a whole.
+fn merge_checkpoints(matches: &clap::ArgMatches) {
+    let output_file = matches.value_of("output").unwrap();
+    let checkpoints: Vec<_> = matches
+        .values_of("inputs")
+        .unwrap()
+        .map(|input_file| {
+            let checkpoint = load_checkpoint(input_file);
+            (input_file, checkpoint)
+        })
+        .collect();
+
+    let (first_file, first) = &checkpoints[0];
+    for (input_file, checkpoint) in checkpoints.iter().skip(1) {
+        if checkpoint.image_size() != first.image_size() {
+            println!(
+                "{} is {:?} but {} is {:?}",
+                input_file,
+                checkpoint.image_size(),
+                first_file,
+                first.image_size()
+            );
+            std::process::exit(1);
+        }
+        if checkpoint.scene_hash != first.scene_hash {
+            println!(
+                "{} and {} are renders of different scenes",
+                input_file, first_file
+            );
+            println!("{}", SCENE_COMPARISON);
+            std::process::exit(1);
+        }
+    }
+
+    // Adaptive renders can stop anywhere between the minimum and maximum passes, but every
+    // finished render has had at least the minimum
+    for (input_file, checkpoint) in &checkpoints {
+        let min_passes = checkpoint.settings.round_passes();
+        if checkpoint.passes_done < min_passes {
+            println!(
+                "Warning: {} only has {} of at least {} passes, so its render didn't finish",
+                input_file, checkpoint.passes_done, min_passes
+            );
+        }
+    }
+
+    let mut seeds: Vec<_> = checkpoints.iter().map(|(_, c)| c.settings.seed).collect();
+    seeds.sort_unstable();
+    seeds.dedup();
+    if seeds.len() < checkpoints.len() {
+        println!("Warning: some of the renders share a seed, so their samples are the same");
+    }
+
+    let (width, height) = first.image_size();
+    let passes: usize = checkpoints.iter().map(|(_, c)| c.passes_done).sum();
+    println!(
+        "Merging {} renders of scene \"{}\" at ({}, {}), {} passes in all",
+        checkpoints.len(),
+        first.scene_name,
+        width,
+        height,
+        passes
+    );
+
+    let crop = first.settings.crop;
+    let crop = crop.filter(|_| checkpoints.iter().all(|(_, c)| c.settings.crop == crop));
+
+    let mut outputs = checkpoints
+        .into_iter()
+        .map(|(_, checkpoint)| checkpoint.into_output());
+    let first = outputs.next().unwrap();
+    let merged = outputs.fold(first, |sum, output| sum + output);
+
+    let output = output_settings(matches);
+    if output.denoiser.is_some() && merged.aovs.is_none() {
+        println!("Warning: not all of the renders have AOVs, so the denoiser can only use color");
+    }
+    if let Err(e) = save_output(&merged, crop, output_file, &output) {
+        println!("Failed to write output: {}", e);
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let matches = command_line();
 
+    if let Some(merge_matches) = matches.subcommand_matches("merge") {
+        merge_checkpoints(merge_matches);
+        return;
+    }
+
     let width = matches
         .value_of("width")
         .and_then(|v| v.parse::<usize>().ok())
@@ -874,14 +1671,255 @@ async fn main() {
         .value_of("threads")
         .and_then(|v| v.parse::<usize>().ok())
         .unwrap_or(DEFAULT_THREADS);
+    let noise_threshold = matches
+        .value_of("noise-threshold")
+        .and_then(|v| v.parse::<FloatType>().ok());
+    let max_passes = matches
+        .value_of("max-passes")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_MAX_PASSES);
+    let max_seconds = matches
+        .value_of("max-seconds")
+        .and_then(|v| v.parse::<f64>().ok());
+    let passes_per_round = matches
+        .value_of("passes-per-round")
+        .and_then(|v| v.parse::<usize>().ok());
+    let preview_file = matches.value_of("preview").map(|v| v.to_string());
+    let max_depth = matches
+        .value_of("max-depth")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_MAX_DEPTH);
+    let min_depth = matches
+        .value_of("min-depth")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_MIN_DEPTH);
+    let russian_roulette = matches
+        .value_of("russian-roulette")
+        .and_then(|v| v.parse::<bool>().ok())
+        .unwrap_or(DEFAULT_RUSSIAN_ROULETTE);
+    let max_sample_luminance = matches
+        .value_of("max-sample-luminance")
+        .and_then(|v| v.parse::<FloatType>().ok());
+    let outlier_sigma = matches
+        .value_of("outlier-sigma")
+        .and_then(|v| v.parse::<FloatType>().ok());
+    let seed = matches
+        .value_of("seed")
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_SEED);
+    let sampler =
+        raster::SamplerKind::from_name(matches.value_of("sampler").unwrap_or(DEFAULT_SAMPLER))
+            .unwrap();
+    let filter_kind =
+        raster::FilterKind::from_name(matches.value_of("filter").unwrap_or(DEFAULT_FILTER))
+            .unwrap();
+    let filter = raster::PixelFilter {
+        radius: matches
+            .value_of("filter-radius")
+            .and_then(|v| v.parse::<FloatType>().ok())
+            .unwrap_or_else(|| filter_kind.default_radius()),
+        ..raster::PixelFilter::new(filter_kind)
+    };
+    let (t0, t1) = match matches.value_of("shutter") {
+        Some(shutter) => {
+            let times: Vec<_> = shutter
+                .split(',')
+                .map(|v| v.trim().parse::<FloatType>().ok())
+                .collect();
+            match times.as_slice() {
+                [Some(open), Some(close)] if open <= close => (*open, *close),
+                _ => {
+                    println!(
+                        "The shutter should be open,close with open no later than close, not {}",
+                        shutter
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => (0.0, 1.0),
+    };
+
+    let stereo = matches.value_of("stereo").map(|layout| raster::StereoRig {
+        interocular: matches
+            .value_of("interocular")
+            .and_then(|v| v.parse::<FloatType>().ok())
+            .unwrap_or(DEFAULT_INTEROCULAR),
+        convergence: matches
+            .value_of("convergence")
+            .and_then(|v| v.parse::<FloatType>().ok()),
+        layout: raster::StereoLayout::from_name(layout).unwrap(),
+    });
+
+    let aperture = if [
+        "aperture-blades",
+        "aperture-rotation",
+        "cats-eye",
+        "aperture-mask",
+    ]
+    .iter()
+    .any(|name| matches.is_present(name))
+    {
+        let mask = matches.value_of("aperture-mask").map(|file| {
+            let image = image::open(file).expect("Failed to read aperture mask");
+            Arc::new(raster::ApertureMask::from_image(&image).expect("Unusable aperture mask"))
+        });
+        Some(raster::Aperture {
+            blades: matches
+                .value_of("aperture-blades")
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(0),
+            rotation: Deg(matches
+                .value_of("aperture-rotation")
+                .and_then(|v| v.parse::<FloatType>().ok())
+                .unwrap_or(0.0))
+            .into(),
+            cats_eye: matches
+                .value_of("cats-eye")
+                .and_then(|v| v.parse::<FloatType>().ok())
+                .unwrap_or(0.0),
+            mask,
+        })
+    } else {
+        None
+    };
+
+    let shutter = if ["shutter-curve", "shutter-ramp", "rolling-shutter"]
+        .iter()
+        .any(|name| matches.is_present(name))
+    {
+        let default_shutter = raster::Shutter::default();
+        Some(raster::Shutter {
+            curve: raster::ShutterCurve::from_name(
+                matches
+                    .value_of("shutter-curve")
+                    .unwrap_or(DEFAULT_SHUTTER_CURVE),
+            )
+            .unwrap(),
+            ramp: matches
+                .value_of("shutter-ramp")
+                .and_then(|v| v.parse::<FloatType>().ok())
+                .unwrap_or(default_shutter.ramp),
+            rolling: matches
+                .value_of("rolling-shutter")
+                .and_then(|v| v.parse::<FloatType>().ok())
+                .unwrap_or(default_shutter.rolling),
+        })
+    } else {
+        None
+    };
+
+    let output = Arc::new(output_settings(&matches));
+    let checkpoint_file = matches.value_of("checkpoint").map(|v| v.to_string());
+    let checkpoint_seconds = matches
+        .value_of("checkpoint-seconds")
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or(DEFAULT_CHECKPOINT_SECONDS);
+    let resume_checkpoint = matches.value_of("resume").map(load_checkpoint);
+
+    let crop = match (
+        matches.value_of("crop"),
+        matches.value_of("crop-normalized"),
+    ) {
+        (Some(crop), _) => Some(
+            parse_crop::<usize>(crop)
+                .map(|(x, y, w, h)| raster::CropWindow::new(x, y, w, h))
+                .expect("The crop window should be x,y,width,height"),
+        ),
+        (_, Some(crop)) => Some(raster::CropWindow::normalized(
+            parse_crop::<FloatType>(crop).expect("The crop window should be x,y,width,height"),
+            width,
+            height,
+        )),
+        _ => None,
+    };
+
+    let settings = raster::RenderSettings {
+        noise_threshold,
+        max_passes,
+        passes_per_round,
+        max_depth,
+        min_depth,
+        russian_roulette,
+        max_sample_luminance,
+        outlier_sigma,
+        seed,
+        sampler,
+        filter,
+        crop,
+        aovs: output.aovs || output.denoiser.is_some(),
+        ..raster::RenderSettings::new(threads, min_passes)
+    };
+
+    // Anything that changes which samples get traced has to come from the checkpoint when we're
+    // resuming, except that the render can be made to go on for longer
+    let (scene_name, (width, height), settings) = match &resume_checkpoint {
+        Some(checkpoint) => {
+            let mut resume_settings = checkpoint.settings.clone();
+            if matches.is_present("min-passes") {
+                resume_settings.min_passes = min_passes;
+            }
+            if matches.is_present("max-passes") {
+                resume_settings.max_passes = max_passes;
+            }
+            if output.aovs || output.denoiser.is_some() {
+                resume_settings.aovs = true;
+            }
+            (
+                checkpoint.scene_name.clone(),
+                checkpoint.image_size(),
+                resume_settings,
+            )
+        }
+        None => (
+            matches
+                .value_of("scene")
+                .unwrap_or(BUILTIN_SCENES[0].0)
+                .to_string(),
+            (width, height),
+            settings,
+        ),
+    };
 
-    let scene_name = matches.value_of("scene").unwrap_or(BUILTIN_SCENES[0].0);
-    let (scene_name, scene_function) = BUILTIN_SCENES.iter().find(|a| a.0 == scene_name).unwrap();
+    let settings_crop = settings.crop.map(|crop| crop.clip(width, height));
+    if matches!(settings_crop, Some(crop) if crop.is_empty()) {
+        println!("The crop window doesn't cover any of the image");
+        std::process::exit(1);
+    }
 
-    let (camera, sky, shapes) = scene_function(width, height);
+    let (scene_name, scene_function) = BUILTIN_SCENES
+        .iter()
+        .find(|a| a.0 == scene_name)
+        .expect("Unknown scene");
+
+    // Some of the scenes are built randomly, so they need seeding too
+    seed_random(settings.seed);
+    // Stereo scenes are made for one eye, and then the camera is turned into a pair of them
+    let (camera, sky, shapes) = match stereo {
+        Some(stereo) => {
+            let (eye_width, eye_height) = stereo.eye_size(width, height);
+            let (camera, sky, shapes) = scene_function(eye_width, eye_height);
+            (camera.with_stereo(stereo), sky, shapes)
+        }
+        None => scene_function(width, height),
+    };
+    let camera = match aperture {
+        Some(aperture) => camera.with_aperture(aperture),
+        None => camera,
+    };
+    let camera = match shutter {
+        Some(shutter) => camera.with_shutter(shutter),
+        None => camera,
+    };
     let scene = raster::Scene::new(camera, sky, shapes);
 
-    let (t0, t1) = (0.0, 1.0);
+    if let Some(checkpoint) = &resume_checkpoint {
+        if checkpoint.scene_hash != scene.fingerprint(t0, t1) {
+            println!("The checkpoint is of a different version of the scene, so can't be resumed");
+            println!("{}", SCENE_COMPARISON);
+            std::process::exit(1);
+        }
+    }
 
     println!(
         "Rendering scene \"{}\" at ({}, {})",
@@ -889,56 +1927,157 @@ async fn main() {
     );
     println!(
         "Using {} threads, with a minimum of {} passes per pixel",
-        threads, min_passes
+        settings.thread_count(),
+        settings.min_passes()
     );
+    if let Some(crop) = settings_crop {
+        println!(
+            "Cropped to ({}, {}) at ({}, {})",
+            crop.width, crop.height, crop.x, crop.y
+        );
+    }
+    if let Some(noise_threshold) = settings.noise_threshold {
+        println!(
+            "Sampling adaptively to a noise threshold of {}, up to {} passes per pixel",
+            noise_threshold,
+            settings.max_passes()
+        );
+    }
+
+    let passes_already_done = resume_checkpoint.as_ref().map_or(0, |c| c.passes_done);
+    if resume_checkpoint.is_some() {
+        println!("Resuming after {} passes", passes_already_done);
+    }
 
-    let expected_pass_count = ((min_passes + threads - 1) / threads) * threads;
+    let expected_pass_count = settings
+        .round_passes()
+        .saturating_sub(passes_already_done)
+        .max(1);
     let expected_pixel_count = width * height * expected_pass_count;
 
+    let cancellation = settings.cancellation.clone();
+    if let Some(max_seconds) = max_seconds {
+        let cancellation = cancellation.clone();
+        tokio::spawn(async move {
+            tokio::time::delay_for(std::time::Duration::from_secs_f64(max_seconds)).await;
+            cancellation.cancel();
+        });
+    }
+
     let start_time = std::time::Instant::now();
     let stats = Arc::new(RwLock::new(raster::TracingStats::new()));
 
-    tokio::pin! {
-        let scanner = raster::scan(scene, (width, height), t0, t1, threads, min_passes, stats.clone());
-    }
-    let mut interval = tokio::time::interval(std::time::Duration::from_secs(10));
-
-    let vector_image = loop {
-        tokio::select! {
-            _ = interval.tick() => {
-                let stats_value = stats.read().unwrap().get_stats();
-                let done_ratio = stats_value.pixels as f64 / expected_pixel_count as f64;
-                let elapsed_time = start_time.elapsed().as_secs_f64();
-                let estimated_time = (elapsed_time / done_ratio) - elapsed_time;
-                println!("Elapsed time: {} seconds", elapsed_time);
-                println!("{}% complete, estimated {} remaining", done_ratio * 100.0, estimated_time);
-                println!("Tracing stats: {:#?}", stats_value);
+    // Previews are written out by a thread of their own, so that the render never waits for
+    // them. If it falls behind it skips straight to the newest one.
+    let (preview_sender, preview_receiver) = std::sync::mpsc::channel::<raster::VectorImage>();
+    let preview_writer = preview_file.clone().map(|preview_file| {
+        let output = output.clone();
+        tokio::task::spawn_blocking(move || {
+            while let Ok(mut preview) = preview_receiver.recv() {
+                while let Ok(newer) = preview_receiver.try_recv() {
+                    preview = newer;
+                }
+                if let Err(e) = save_image(&preview, None, &preview_file, &output) {
+                    println!("Failed to write preview: {}", e);
+                }
+            }
+        })
+    });
+
+    let mut last_checkpoint_time = std::time::Instant::now();
+    let on_progress = |progress: raster::RenderProgress| {
+        if preview_file.is_some() {
+            let preview = match settings_crop {
+                Some(crop) if !output.full_frame => progress.image.crop(crop),
+                _ => progress.image.clone(),
+            };
+            let _ = preview_sender.send(preview);
+        }
+
+        // The last round is always saved, so that a finished render leaves a finished checkpoint
+        if let Some(checkpoint_file) = &checkpoint_file {
+            if progress.finished
+                || last_checkpoint_time.elapsed().as_secs_f64() >= checkpoint_seconds
+            {
+                if let Some(checkpoint) = progress.checkpoint(scene_name) {
+                    match checkpoint.save(checkpoint_file) {
+                        Ok(()) => {
+                            println!("Saved checkpoint after {} passes", progress.passes_done)
+                        }
+                        Err(e) => println!("Failed to write checkpoint: {}", e),
+                    }
+                    last_checkpoint_time = std::time::Instant::now();
+                }
             }
+        }
+    };
+
+    let render_output = {
+        tokio::pin! {
+            let scanner = match resume_checkpoint {
+                Some(checkpoint) => futures::future::Either::Left(raster::scan_resume(
+                    scene,
+                    checkpoint,
+                    t0,
+                    t1,
+                    settings,
+                    stats.clone(),
+                    on_progress,
+                )),
+                None => futures::future::Either::Right(raster::scan_progressive(
+                    scene,
+                    (width, height),
+                    t0,
+                    t1,
+                    settings,
+                    stats.clone(),
+                    on_progress,
+                )),
+            };
+        }
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(10));
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let stats_value = stats.read().unwrap().get_stats();
+                    let done_ratio = stats_value.pixels as f64 / expected_pixel_count as f64;
+                    let elapsed_time = start_time.elapsed().as_secs_f64();
+                    let estimated_time = (elapsed_time / done_ratio) - elapsed_time;
+                    println!("Elapsed time: {} seconds", elapsed_time);
+                    println!("{}% complete, estimated {} remaining", done_ratio * 100.0, estimated_time);
+                    println!("Tracing stats: {:#?}", stats_value);
+                }
 
-            image = &mut scanner => break image,
+                _ = tokio::signal::ctrl_c(), if !cancellation.is_cancelled() => {
+                    println!("Stopping, the image so far will still be written");
+                    cancellation.cancel();
+                }
+
+                render_output = &mut scanner => break render_output,
+            }
         }
     };
 
+    // Let the last preview finish before moving on
+    drop(preview_sender);
+    if let Some(preview_writer) = preview_writer {
+        preview_writer.await.unwrap();
+    }
+
     let stats_value = stats.read().unwrap().get_stats();
-    println!("FINISHED");
+    if cancellation.is_cancelled() {
+        println!("STOPPED EARLY");
+    } else {
+        println!("FINISHED");
+    }
     println!(
         "Elapsed time: {} seconds",
         start_time.elapsed().as_secs_f64()
     );
     println!("Tracing stats: {:#?}", stats_value);
 
-    let mut surf = RgbImage::new(width as u32, height as u32);
-
-    vector_image
-        .pixels()
-        .zip(surf.pixels_mut())
-        .fold((), |_, (src, dst)| {
-            let color = src / src.w;
-            let color: Color = color.try_into().unwrap();
-            *dst = color.gamma(2.0).into();
-        });
-
-    if let Err(e) = surf.save(output_file) {
+    if let Err(e) = save_output(&render_output, settings_crop, &output_file, &output) {
         println!("Failed to write output: {}", e);
     }
 }
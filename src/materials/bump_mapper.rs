@@ -1,7 +1,7 @@
 use super::surface_mapper::SurfaceMappingMaterial;
 use crate::{
-    factories::*, math::*, utils::*, Color, GeometryHitResult, IntersectResult, Material,
-    PartialScatterResult, Ray, ScatterResult, SurfaceMapper, Texture,
+    factories::*, math::*, Color, GeometryHitResult, IntersectResult, Material,
+    PartialScatterResult, Ray, Sampler, ScatterResult, SurfaceMapper, Texture,
 };
 use std::convert::TryInto;
 
@@ -35,13 +35,19 @@ impl<T: Texture> SurfaceMapper for BumpMapper<T> {
 pub struct SurfaceNormalDebugMaterial();
 
 impl Material for SurfaceNormalDebugMaterial {
-    fn scatter(&self, ray_in: &Ray, hit_record: GeometryHitResult) -> Option<ScatterResult> {
-        let target = hit_record.hit_point() + hit_record.surface_normal() + random_unit_vector();
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: GeometryHitResult,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
+        let target = hit_record.hit_point() + hit_record.surface_normal() + sampler.unit_vector();
         let color = (hit_record.surface_normal() * 2.0) + vec3(1.0, 1.0, 1.0);
         let color: Color = color.extend(1.0).try_into().unwrap();
         Some(ScatterResult {
             partial: PartialScatterResult {
                 attenuation: cgmath::Vector4::from(color).truncate(),
+                pdf: None,
             },
             scattered: Ray::new(
                 hit_record.hit_point(),
@@ -1,4 +1,4 @@
-use crate::{BaseMaterial, Color, GeometryHitResult, IntersectResult, Ray};
+use crate::{BaseMaterial, Color, GeometryHitResult, IntersectResult, Ray, Sampler};
 
 use super::material::BaseMaterialScatterResult;
 
@@ -13,10 +13,18 @@ pub enum DebugMaterial {
 impl BaseMaterial for DebugMaterial {
     fn base_scatter(
         &self,
-        _ray_in: &Ray,
+        ray_in: &Ray,
         hit_record: GeometryHitResult,
+        _sampler: &mut dyn Sampler,
     ) -> BaseMaterialScatterResult {
-        let color = match self {
+        BaseMaterialScatterResult {
+            emitted: self.base_emitted(ray_in, hit_record),
+            scatter: None,
+        }
+    }
+
+    fn base_emitted(&self, _ray_in: &Ray, hit_record: GeometryHitResult) -> Color {
+        match self {
             Self::Uv => {
                 let uv = hit_record.uv();
                 Color([uv.x, uv.y, 0.0, 0.0])
@@ -51,11 +59,6 @@ impl BaseMaterial for DebugMaterial {
                     0.0,
                 ])
             }
-        };
-
-        BaseMaterialScatterResult {
-            emitted: color,
-            scatter: None,
         }
     }
 }
@@ -1,8 +1,7 @@
 use super::utils::*;
 use super::{Material, PartialScatterResult, ScatterResult};
-use crate::utils::*;
 use crate::{math::*, GeometryHitResult};
-use crate::{IntersectResult, Ray};
+use crate::{IntersectResult, Ray, Sampler};
 
 #[derive(Clone, Debug)]
 pub struct Dielectric(FloatType);
@@ -18,7 +17,12 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, hit_record: GeometryHitResult) -> Option<ScatterResult> {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: GeometryHitResult,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
         let etai_over_etat = if hit_record.front_face() {
             1.0 / self.refractive_index()
         } else {
@@ -29,13 +33,14 @@ impl Material for Dielectric {
         let cos_theta = -unit_ray_direction.dot(hit_record.surface_normal()).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         if etai_over_etat * sin_theta > 1.0
-            || random_in_range(0.0, 1.0) < schlick(cos_theta, etai_over_etat)
+            || sampler.next_1d() < schlick(cos_theta, etai_over_etat)
         {
             let reflected = reflect(unit_ray_direction, hit_record.surface_normal());
 
             Some(ScatterResult {
                 partial: PartialScatterResult {
                     attenuation: vec3(1.0, 1.0, 1.0),
+                    pdf: None,
                 },
                 scattered: Ray::new(hit_record.hit_point(), reflected, ray_in.time()),
             })
@@ -49,6 +54,7 @@ impl Material for Dielectric {
             Some(ScatterResult {
                 partial: PartialScatterResult {
                     attenuation: vec3(1.0, 1.0, 1.0),
+                    pdf: None,
                 },
                 scattered: Ray::new(hit_record.hit_point(), refracted, ray_in.time()),
             })
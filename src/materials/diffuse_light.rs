@@ -1,6 +1,6 @@
 use super::{Material, ScatterResult};
 use crate::{math::*, GeometryHitResult};
-use crate::{Color, Ray, Texture};
+use crate::{Color, Ray, Sampler, Texture};
 
 #[derive(Debug, Clone)]
 pub struct DiffuseLight<T: 'static + Texture + Clone>(T);
@@ -20,9 +20,18 @@ impl<T: 'static + Texture + Clone> Material for DiffuseLight<T> {
         self.emit().value(p, uv)
     }
 
-    fn scatter(&self, _ray_in: &Ray, _hit_record: GeometryHitResult) -> Option<ScatterResult> {
+    fn scatter(
+        &self,
+        _ray_in: &Ray,
+        _hit_record: GeometryHitResult,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
         None
     }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
 }
 
 pub mod factories {
@@ -1,5 +1,5 @@
-use super::{Material, ScatterResult};
-use crate::{GeometryHitResult, Ray};
+use super::{Material, PartialScatterResult, ScatterResult};
+use crate::{math::*, Color, GeometryHitResult, Ray, Sampler};
 
 #[derive(Debug, Clone)]
 #[repr(transparent)]
@@ -12,9 +12,28 @@ impl<M: Material + Clone> InvertNormal<M> {
 }
 
 impl<M: Material + Clone> Material for InvertNormal<M> {
-    fn scatter(&self, ray_in: &Ray, mut hit_record: GeometryHitResult) -> Option<ScatterResult> {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        mut hit_record: GeometryHitResult,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
         hit_record.front_face = !hit_record.front_face;
-        self.0.scatter(ray_in, hit_record)
+        self.0.scatter(ray_in, hit_record, sampler)
+    }
+
+    fn scatter_towards(
+        &self,
+        ray_in: &Ray,
+        mut hit_record: GeometryHitResult,
+        direction: Vector3,
+    ) -> Option<PartialScatterResult> {
+        hit_record.front_face = !hit_record.front_face;
+        self.0.scatter_towards(ray_in, hit_record, direction)
+    }
+
+    fn albedo(&self, hit_record: &GeometryHitResult) -> Color {
+        self.0.albedo(hit_record)
     }
 }
 
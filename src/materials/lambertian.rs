@@ -1,6 +1,6 @@
 use super::{Material, PartialScatterResult, ScatterResult};
-use crate::{utils::*, GeometryHitResult};
-use crate::{IntersectResult, Ray, Texture};
+use crate::{math::*, GeometryHitResult};
+use crate::{Color, IntersectResult, Ray, Sampler, Texture};
 
 #[derive(Clone, Debug)]
 pub struct Lambertian<T: 'static + Texture + Clone>(T);
@@ -16,12 +16,21 @@ impl<T: 'static + Texture + Clone> Lambertian<T> {
 }
 
 impl<T: 'static + Texture + Clone> Material for Lambertian<T> {
-    fn scatter(&self, ray_in: &Ray, hit_record: GeometryHitResult) -> Option<ScatterResult> {
-        let target = hit_record.hit_point() + hit_record.surface_normal() + random_unit_vector();
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: GeometryHitResult,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
+        let target = hit_record.hit_point() + hit_record.surface_normal() + sampler.unit_vector();
         let color = self.albedo().value(hit_record.hit_point(), hit_record.uv());
         Some(ScatterResult {
             partial: PartialScatterResult {
                 attenuation: cgmath::Vector4::from(color).truncate(),
+                pdf: Some(cosine_pdf(
+                    hit_record.surface_normal(),
+                    target - hit_record.hit_point(),
+                )),
             },
             scattered: Ray::new(
                 hit_record.hit_point(),
@@ -30,6 +39,30 @@ impl<T: 'static + Texture + Clone> Material for Lambertian<T> {
             ),
         })
     }
+
+    fn scatter_towards(
+        &self,
+        _ray_in: &Ray,
+        hit_record: GeometryHitResult,
+        direction: Vector3,
+    ) -> Option<PartialScatterResult> {
+        let pdf = cosine_pdf(hit_record.surface_normal(), direction);
+        let color = self.albedo().value(hit_record.hit_point(), hit_record.uv());
+        Some(PartialScatterResult {
+            attenuation: cgmath::Vector4::from(color).truncate() * pdf,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn albedo(&self, hit_record: &GeometryHitResult) -> Color {
+        self.albedo().value(hit_record.hit_point(), hit_record.uv())
+    }
+}
+
+// Picking the point on a unit sphere sitting on the surface gives us directions with a density
+// of cos(theta) / pi
+fn cosine_pdf(surface_normal: Vector3, direction: Vector3) -> FloatType {
+    surface_normal.dot(direction.normalize()).max(0.0) / constants::PI
 }
 
 pub mod factories {
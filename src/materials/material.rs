@@ -1,8 +1,11 @@
-use crate::{constants, math::*, Color, GeometryHitResult, IntersectResult, Ray};
+use crate::{constants, math::*, Color, GeometryHitResult, IntersectResult, Ray, Sampler};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct PartialScatterResult {
     pub attenuation: Vector3,
+    // The probability density (per unit solid angle) of scattering in this direction. Specular
+    // materials, which only ever scatter in one direction, have no density and leave this empty.
+    pub pdf: Option<FloatType>,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -28,15 +31,76 @@ pub trait BaseMaterial: Sync + Send + std::fmt::Debug {
         &self,
         ray_in: &Ray,
         hit_record: GeometryHitResult,
+        sampler: &mut dyn Sampler,
     ) -> BaseMaterialScatterResult;
+
+    fn base_emitted(&self, ray_in: &Ray, hit_record: GeometryHitResult) -> Color;
+
+    fn base_scatter_towards(
+        &self,
+        _ray_in: &Ray,
+        _hit_record: GeometryHitResult,
+        _direction: Vector3,
+    ) -> Option<PartialScatterResult> {
+        None
+    }
+
+    fn base_is_emissive(&self) -> bool {
+        false
+    }
+
+    fn base_albedo(&self, _hit_record: &GeometryHitResult) -> Color {
+        constants::WHITE
+    }
+
+    fn base_shading_normal(&self, hit_record: &GeometryHitResult) -> Vector3 {
+        hit_record.surface_normal()
+    }
 }
 
 pub trait Material: Sync + Send + std::fmt::Debug {
-    fn scatter(&self, ray_in: &Ray, hit_record: GeometryHitResult) -> Option<ScatterResult>;
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: GeometryHitResult,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult>;
 
     fn emitted(&self, _p: Point3, _uv: Point2) -> Color {
         constants::BLACK
     }
+
+    // Work out how much of the light arriving from direction is scattered back along ray_in,
+    // including the cosine term. Unlike scatter, the attenuation here has not been divided by
+    // the probability of picking that direction, but the pdf is the one that scatter would have
+    // picked it with. Materials that only scatter in specific directions (mirrors, glass) cannot
+    // answer this and return None, which means the renderer will not try to sample lights from
+    // them. Anything that returns a pdf from scatter must implement this.
+    fn scatter_towards(
+        &self,
+        _ray_in: &Ray,
+        _hit_record: GeometryHitResult,
+        _direction: Vector3,
+    ) -> Option<PartialScatterResult> {
+        None
+    }
+
+    // Emissive materials are gathered up when the scene is prepared so that they can be
+    // sampled directly
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    // The color of the surface, and the direction it faces once things like bump maps have been
+    // applied. These only feed the auxiliary buffers that get written out alongside the image,
+    // so they don't have to be exact, and anything that doesn't know reflects everything.
+    fn albedo(&self, _hit_record: &GeometryHitResult) -> Color {
+        constants::WHITE
+    }
+
+    fn shading_normal(&self, hit_record: &GeometryHitResult) -> Vector3 {
+        hit_record.surface_normal()
+    }
 }
 
 impl<T: Material> BaseMaterial for T {
@@ -44,10 +108,36 @@ impl<T: Material> BaseMaterial for T {
         &self,
         ray_in: &Ray,
         hit_record: GeometryHitResult,
+        sampler: &mut dyn Sampler,
     ) -> BaseMaterialScatterResult {
         let emitted = self.emitted(hit_record.hit_point(), hit_record.uv());
-        let scatter = self.scatter(ray_in, hit_record);
+        let scatter = self.scatter(ray_in, hit_record, sampler);
 
         BaseMaterialScatterResult { emitted, scatter }
     }
+
+    fn base_emitted(&self, _ray_in: &Ray, hit_record: GeometryHitResult) -> Color {
+        self.emitted(hit_record.hit_point(), hit_record.uv())
+    }
+
+    fn base_scatter_towards(
+        &self,
+        ray_in: &Ray,
+        hit_record: GeometryHitResult,
+        direction: Vector3,
+    ) -> Option<PartialScatterResult> {
+        self.scatter_towards(ray_in, hit_record, direction)
+    }
+
+    fn base_is_emissive(&self) -> bool {
+        self.is_emissive()
+    }
+
+    fn base_albedo(&self, hit_record: &GeometryHitResult) -> Color {
+        self.albedo(hit_record)
+    }
+
+    fn base_shading_normal(&self, hit_record: &GeometryHitResult) -> Vector3 {
+        self.shading_normal(hit_record)
+    }
 }
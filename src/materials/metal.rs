@@ -1,8 +1,7 @@
 use super::utils::*;
 use super::{Material, PartialScatterResult, ScatterResult};
-use crate::utils::*;
 use crate::{math::*, GeometryHitResult};
-use crate::{Color, IntersectResult, Ray, Texture};
+use crate::{Color, IntersectResult, Ray, Sampler, Texture};
 
 #[derive(Debug)]
 pub struct Metal<T: Texture>(T, FloatType);
@@ -19,6 +18,27 @@ impl<T: Texture> Metal<T> {
     pub fn fuzz(&self) -> FloatType {
         self.1
     }
+
+    // Scattered directions come from picking a point in a sphere of radius fuzz around the tip of
+    // the reflected vector, so the density of a direction is how much of that sphere lies along
+    // it. We get that by integrating t^2 over the part of the ray that is inside the sphere. With
+    // no fuzz at all we are a perfect mirror and there is no density.
+    fn fuzz_pdf(&self, reflected: Vector3, direction: Vector3) -> Option<FloatType> {
+        let fuzz = self.fuzz();
+        if fuzz <= 0.0 {
+            return None;
+        }
+
+        let cosine = reflected.dot(direction.normalize());
+        let discriminant = (cosine * cosine) - 1.0 + (fuzz * fuzz);
+        if cosine <= 0.0 || discriminant <= 0.0 {
+            return Some(0.0);
+        }
+
+        let t0 = (cosine - discriminant.sqrt()).max(0.0);
+        let t1 = cosine + discriminant.sqrt();
+        Some(((t1 * t1 * t1) - (t0 * t0 * t0)) / (4.0 * constants::PI * fuzz * fuzz * fuzz))
+    }
 }
 
 impl<T: Texture + Clone> Clone for Metal<T> {
@@ -28,23 +48,59 @@ impl<T: Texture + Clone> Clone for Metal<T> {
 }
 
 impl<T: Texture> Material for Metal<T> {
-    fn scatter(&self, ray_in: &Ray, hit_record: GeometryHitResult) -> Option<ScatterResult> {
-        let reflected = reflect(ray_in.direction().normalize(), hit_record.surface_normal())
-            + self.fuzz() * random_in_unit_sphere();
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: GeometryHitResult,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
+        let reflected = reflect(ray_in.direction().normalize(), hit_record.surface_normal());
+        let scattered = reflected + self.fuzz() * sampler.in_unit_sphere();
         let color = self
             .texture()
             .value(hit_record.hit_point(), hit_record.uv());
-        if reflected.dot(hit_record.surface_normal()) > 0.0 {
+        if scattered.dot(hit_record.surface_normal()) > 0.0 {
             Some(ScatterResult {
                 partial: PartialScatterResult {
                     attenuation: cgmath::Vector4::from(color).truncate(),
+                    pdf: self.fuzz_pdf(reflected, scattered),
                 },
-                scattered: Ray::new(hit_record.hit_point(), reflected.normalize(), ray_in.time()),
+                scattered: Ray::new(hit_record.hit_point(), scattered.normalize(), ray_in.time()),
             })
         } else {
             None
         }
     }
+
+    fn scatter_towards(
+        &self,
+        ray_in: &Ray,
+        hit_record: GeometryHitResult,
+        direction: Vector3,
+    ) -> Option<PartialScatterResult> {
+        let reflected = reflect(ray_in.direction().normalize(), hit_record.surface_normal());
+        let pdf = self.fuzz_pdf(reflected, direction)?;
+
+        // Anything that ends up below the surface gets absorbed
+        let attenuation = if direction.dot(hit_record.surface_normal()) > 0.0 {
+            let color = self
+                .texture()
+                .value(hit_record.hit_point(), hit_record.uv());
+            cgmath::Vector4::from(color).truncate() * pdf
+        } else {
+            Vector3::zero()
+        };
+
+        Some(PartialScatterResult {
+            attenuation,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn albedo(&self, hit_record: &GeometryHitResult) -> Color {
+        self.texture()
+            .value(hit_record.hit_point(), hit_record.uv())
+    }
 }
 
 pub mod factories {
@@ -60,3 +116,36 @@ pub mod factories {
         metal_with_texture(solid_texture(color), fuzz)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::factories::*;
+
+    #[test]
+    fn test_fuzz_pdf_integrates_to_one() {
+        let reflected = vec3(0.0, 0.0, 1.0);
+        let steps = 100_000;
+
+        for fuzz in [0.1, 0.3, 1.0].iter() {
+            let material = metal(crate::constants::WHITE, *fuzz);
+
+            // Integrate over the sphere in bands of equal z, which all have the same area
+            let total: FloatType = (0..steps)
+                .map(|i| {
+                    let z = -1.0 + (2.0 * (i as FloatType + 0.5) / (steps as FloatType));
+                    let direction = vec3((1.0 - z * z).sqrt(), 0.0, z);
+                    material.fuzz_pdf(reflected, direction).unwrap()
+                })
+                .sum::<FloatType>()
+                * (4.0 * constants::PI / (steps as FloatType));
+
+            assert!(
+                (total - 1.0).abs() < 0.01,
+                "fuzz {} integrates to {}",
+                fuzz,
+                total
+            );
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{math::*, Color, GeometryHitResult, Material, Ray, ScatterResult};
+use crate::{
+    math::*, Color, GeometryHitResult, Material, PartialScatterResult, Ray, Sampler, ScatterResult,
+};
 
 pub trait SurfaceMapper: Send + Sync + std::fmt::Debug {
     fn process_hit_result(&self, hit_result: GeometryHitResult) -> GeometryHitResult;
@@ -17,17 +19,47 @@ impl<T: SurfaceMapper + Clone, M: Material + Clone> Clone for SurfaceMappingMate
 }
 
 impl<T: SurfaceMapper, M: Material> Material for SurfaceMappingMaterial<T, M> {
-    fn scatter(&self, ray_in: &Ray, hit_record: GeometryHitResult) -> Option<ScatterResult> {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: GeometryHitResult,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
         let mapped_hit_record = self.0.process_hit_result(hit_record);
 
         self.1
-            .scatter(ray_in, mapped_hit_record)
+            .scatter(ray_in, mapped_hit_record, sampler)
             .map(|scatter_result| self.0.process_scatter_result(scatter_result))
     }
 
     fn emitted(&self, p: Point3, uv: Point2) -> Color {
         self.1.emitted(p, uv)
     }
+
+    fn scatter_towards(
+        &self,
+        ray_in: &Ray,
+        hit_record: GeometryHitResult,
+        direction: Vector3,
+    ) -> Option<PartialScatterResult> {
+        let mapped_hit_record = self.0.process_hit_result(hit_record);
+
+        self.1.scatter_towards(ray_in, mapped_hit_record, direction)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.1.is_emissive()
+    }
+
+    fn albedo(&self, hit_record: &GeometryHitResult) -> Color {
+        self.1
+            .albedo(&self.0.process_hit_result(hit_record.clone()))
+    }
+
+    fn shading_normal(&self, hit_record: &GeometryHitResult) -> Vector3 {
+        self.1
+            .shading_normal(&self.0.process_hit_result(hit_record.clone()))
+    }
 }
 
 pub mod factories {
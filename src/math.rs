@@ -1,6 +1,7 @@
 pub type FloatType = f32;
 pub type Point2 = cgmath::Point2<FloatType>;
 pub type Point3 = cgmath::Point3<FloatType>;
+pub type Vector2 = cgmath::Vector2<FloatType>;
 pub type Vector3 = cgmath::Vector3<FloatType>;
 pub type Vector4 = cgmath::Vector4<FloatType>;
 pub type Matrix4 = cgmath::Matrix4<FloatType>;
@@ -0,0 +1,144 @@
+use crate::{math::*, Named};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+impl Named for FilterKind {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("box", FilterKind::Box),
+        ("tent", FilterKind::Tent),
+        ("gaussian", FilterKind::Gaussian),
+        ("mitchell", FilterKind::Mitchell),
+        ("lanczos", FilterKind::Lanczos),
+    ];
+}
+
+impl FilterKind {
+    pub fn default_radius(self) -> FloatType {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.0,
+            Self::Gaussian => 1.5,
+            Self::Mitchell => 2.0,
+            Self::Lanczos => 2.0,
+        }
+    }
+}
+
+// How much a sample counts towards the pixels around it. Each sample is added to every pixel
+// whose center is within the radius of it, weighted by the filter, and each pixel ends up as the
+// weighted average of those. A box with a radius of half a pixel only adds samples to the pixel
+// they were taken in, which is what happens without a filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixelFilter {
+    pub kind: FilterKind,
+    pub radius: FloatType,
+}
+
+impl PixelFilter {
+    pub fn new(kind: FilterKind) -> Self {
+        Self {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    // How many pixels either side of the one a sample was taken in it can reach. Samples are
+    // anywhere up to half a pixel away from the center of their own pixel.
+    pub(crate) fn margin(&self) -> usize {
+        ((self.radius.max(0.0) + 0.5).ceil() as usize).saturating_sub(1)
+    }
+
+    // The filter is separable, so this is the weight along one axis, for a sample the given
+    // distance from the center of a pixel. The range includes the start and not the end, so that
+    // a sample on the edge between two pixels is only counted by one of them.
+    pub fn weight(&self, offset: FloatType) -> FloatType {
+        let radius = self.radius;
+        if offset < -radius || offset >= radius {
+            return 0.0;
+        }
+
+        let distance = offset.abs();
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - (distance / radius),
+
+            // The Gaussian has a standard deviation of a third of the radius, and is moved down so
+            // that it reaches zero at the radius, rather than stopping suddenly there
+            FilterKind::Gaussian => {
+                let alpha = 4.5 / (radius * radius);
+                ((-alpha * distance * distance).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+
+            // Mitchell and Netravali's recommended cubic, with B and C both a third, stretched
+            // out to the radius
+            FilterKind::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let x = 2.0 * distance / radius;
+                let value = if x < 1.0 {
+                    ((12.0 - (9.0 * b) - (6.0 * c)) * x * x * x)
+                        + ((-18.0 + (12.0 * b) + (6.0 * c)) * x * x)
+                        + (6.0 - (2.0 * b))
+                } else {
+                    ((-b - (6.0 * c)) * x * x * x)
+                        + (((6.0 * b) + (30.0 * c)) * x * x)
+                        + (((-12.0 * b) - (48.0 * c)) * x)
+                        + ((8.0 * b) + (24.0 * c))
+                };
+                value / 6.0
+            }
+
+            // A sinc, windowed by a wider sinc that reaches zero at the radius
+            FilterKind::Lanczos => sinc(distance) * sinc(distance / radius),
+        }
+    }
+}
+
+impl Default for PixelFilter {
+    fn default() -> Self {
+        Self::new(FilterKind::Box)
+    }
+}
+
+fn sinc(x: FloatType) -> FloatType {
+    if x < constants::EPSILON {
+        1.0
+    } else {
+        let x = x * constants::PI;
+        x.sin() / x
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_filter_weights() {
+        for (_, kind) in FilterKind::NAMES.iter() {
+            let filter = PixelFilter::new(*kind);
+
+            // Everything is at its biggest in the middle, and nothing reaches past the radius
+            assert!(filter.weight(0.0) > 0.0, "{:?}", kind);
+            assert!(filter.weight(0.0) >= filter.weight(0.25), "{:?}", kind);
+            assert_eq!(filter.weight(filter.radius), 0.0, "{:?}", kind);
+            assert_eq!(filter.weight(-filter.radius - 0.01), 0.0, "{:?}", kind);
+            assert_eq!(filter.weight(0.3), filter.weight(-0.3), "{:?}", kind);
+        }
+
+        // A plain box only ever reaches the pixel the sample was taken in
+        let filter = PixelFilter::default();
+        assert_eq!(filter.margin(), 0);
+        assert_eq!(filter.weight(-0.5), 1.0);
+        assert_eq!(filter.weight(0.5), 0.0);
+
+        assert_eq!(PixelFilter::new(FilterKind::Tent).margin(), 1);
+        assert_eq!(PixelFilter::new(FilterKind::Mitchell).margin(), 2);
+    }
+}
@@ -1,20 +1,23 @@
+use crate::aov::{AovHit, AovImage, AovPixel, AovSample};
+use crate::checkpoint::RenderCheckpoint;
+use crate::color::luminance;
+use crate::tile_scheduler::{Tile, TileScheduler, TileWork, TILE_SIZE};
 use crate::{
     constants,
     math::*,
     scene::{PreparedScene, Scene},
-    utils::*,
-    Color, Intersectable, PartialScatterResult, Ray, RenderStatsAccumulator, RenderStatsCollector,
-    ScatterResult, TracingStats,
+    BaseMaterial, Color, CropWindow, GeometryHitResult, IntersectResult, Intersectable, Ray,
+    RenderSettings, RenderStatsAccumulator, RenderStatsCollector, Sampler, ScatterResult,
+    TracingStats,
 };
 use futures::future::join_all;
-use std::{
-    mem::MaybeUninit,
-    slice::{Chunks, ChunksMut},
-};
+use std::slice::{Chunks, ChunksMut};
 
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
+#[derive(Clone)]
 pub struct VectorImage {
     width: usize,
     data: Box<[cgmath::Vector4<FloatType>]>,
@@ -74,6 +77,14 @@ impl VectorImage {
         }
     }
 
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.data.len().checked_div(self.width).unwrap_or(0)
+    }
+
     pub fn pixels(&self) -> Pixels {
         Pixels {
             chunks: self.data.chunks(1),
@@ -86,6 +97,39 @@ impl VectorImage {
         }
     }
 
+    // The average of the samples in every pixel, going across and then down. Pixels with no
+    // samples at all, which a render that was stopped early can have, come out black.
+    pub fn averaged_pixels(&self) -> impl Iterator<Item = Vector3> + '_ {
+        self.pixels().map(|pixel| {
+            if pixel.w > 0.0 {
+                pixel.truncate() / pixel.w
+            } else {
+                Vector3::zero()
+            }
+        })
+    }
+
+    pub fn crop(&self, window: CropWindow) -> VectorImage {
+        let window = window.clip(self.width(), self.height());
+        Self {
+            width: window.width,
+            data: window.crop(&self.data, self.width),
+        }
+    }
+
+    // Add the samples for a tile, which are stored row by row, onto the image
+    pub fn add_tile(&mut self, tile: &Tile, tile_data: &[cgmath::Vector4<FloatType>]) {
+        debug_assert_eq!(tile_data.len(), tile.pixel_count());
+
+        for (row, src) in tile_data.chunks(tile.width).enumerate() {
+            let start = ((tile.y + row) * self.width) + tile.x;
+            self.data[start..start + tile.width]
+                .iter_mut()
+                .zip(src)
+                .for_each(|(dst, src)| *dst += *src);
+        }
+    }
+
     pub fn enumerate_pixels_mut(&mut self) -> EnumeratePixelsMut {
         let width = self.width;
         EnumeratePixelsMut {
@@ -109,147 +153,1039 @@ impl std::ops::Add for VectorImage {
     }
 }
 
+// Alongside the image we keep some statistics about the samples taken in each pixel, which tell
+// us how noisy it still is. The auxiliary outputs are kept here too, when we've been asked for
+// them.
+struct Accumulator {
+    image: VectorImage,
+    sample_stats: Box<[SampleStats]>,
+    aovs: Option<AovImage>,
+}
+
+// How many samples were taken in a pixel, along with the sums of their brightness and of its
+// square. Filters spread samples out over their neighbours with weights that can be negative, so
+// the sums in the image can't be used for this.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct SampleStats {
+    pub(crate) count: FloatType,
+    pub(crate) luminance: FloatType,
+    pub(crate) luminance_squares: FloatType,
+}
+
+impl SampleStats {
+    fn add_sample(&mut self, luminance: FloatType) {
+        self.count += 1.0;
+        self.luminance += luminance;
+        self.luminance_squares += luminance * luminance;
+    }
+
+    // The variance of the mean brightness of the pixel. There isn't one until the pixel has at
+    // least two samples.
+    pub(crate) fn variance(&self) -> Option<FloatType> {
+        let count = self.count;
+        if count < 2.0 {
+            return None;
+        }
+
+        let mean = self.luminance / count;
+        let variance =
+            ((self.luminance_squares / count) - (mean * mean)).max(0.0) * count / (count - 1.0);
+        Some(variance / count)
+    }
+}
+
+impl std::ops::Add for SampleStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            count: self.count + other.count,
+            luminance: self.luminance + other.luminance,
+            luminance_squares: self.luminance_squares + other.luminance_squares,
+        }
+    }
+}
+
+impl std::ops::AddAssign for SampleStats {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+// What a worker traced for one tile, stored row by row. Filters can spread samples out past the
+// edges of the tile, and what lands outside it is kept separately, along with where it goes.
+struct TileSamples {
+    sums: Vec<cgmath::Vector4<FloatType>>,
+    sample_stats: Vec<SampleStats>,
+    spill: Vec<(usize, cgmath::Vector4<FloatType>)>,
+    aovs: Option<Vec<AovPixel>>,
+}
+
+impl TileSamples {
+    // Put the parts of a tile back together, in the order of their passes. The parts all cover
+    // the same pixels, so everything lines up.
+    fn merge(mut parts: Vec<(usize, TileSamples)>) -> Option<TileSamples> {
+        parts.sort_by_key(|(first_pass, _)| *first_pass);
+        let mut parts = parts.into_iter().map(|(_, part)| part);
+        let mut merged = parts.next()?;
+        for part in parts {
+            for (dst, src) in merged.sums.iter_mut().zip(&part.sums) {
+                *dst += *src;
+            }
+            for (dst, src) in merged.sample_stats.iter_mut().zip(&part.sample_stats) {
+                *dst += *src;
+            }
+            for ((_, dst), (_, src)) in merged.spill.iter_mut().zip(&part.spill) {
+                *dst += *src;
+            }
+            if let (Some(dst), Some(src)) = (&mut merged.aovs, &part.aovs) {
+                dst.iter_mut().zip(src).for_each(|(dst, src)| dst.add(src));
+            }
+        }
+        Some(merged)
+    }
+}
+
+impl Accumulator {
+    fn new(width: usize, height: usize, aovs: bool) -> Self {
+        Self {
+            image: VectorImage::new(width, height),
+            sample_stats: vec![SampleStats::default(); width * height].into_boxed_slice(),
+            aovs: if aovs {
+                Some(AovImage::new(width, height))
+            } else {
+                None
+            },
+        }
+    }
+
+    fn add_tile(&mut self, tile: &Tile, tile_data: &TileSamples) {
+        self.image.add_tile(tile, &tile_data.sums);
+
+        let width = self.image.width;
+        for ((x, y), sample_stats) in tile.pixels().zip(&tile_data.sample_stats) {
+            self.sample_stats[(y * width) + x] += *sample_stats;
+        }
+
+        if let (Some(aovs), Some(tile_aovs)) = (&mut self.aovs, &tile_data.aovs) {
+            aovs.add_tile(tile, tile_aovs);
+        }
+    }
+
+    fn add_spill(&mut self, spill: &[(usize, cgmath::Vector4<FloatType>)]) {
+        for (pixel_index, sum) in spill {
+            self.image.data[*pixel_index] += *sum;
+        }
+    }
+
+    // The standard error of the mean of each pixel, relative to how bright it is. Dark pixels
+    // are compared against a minimum brightness so that we don't chase tiny errors in them.
+    fn relative_errors(&self) -> impl Iterator<Item = FloatType> + '_ {
+        self.sample_stats
+            .iter()
+            .map(|sample_stats| match sample_stats.variance() {
+                Some(variance) => {
+                    let mean = sample_stats.luminance / sample_stats.count;
+                    variance.sqrt() / mean.max(MIN_ERROR_LUMINANCE)
+                }
+                None => constants::INFINITY,
+            })
+    }
+}
+
+const MIN_ERROR_LUMINANCE: FloatType = 0.01;
+
+// What scan_progressive hands to its callback after every round
+pub struct RenderProgress<'a> {
+    // Everything accumulated so far. Pixels can have different numbers of samples in them, so
+    // divide each one by its own alpha.
+    pub image: &'a VectorImage,
+    pub aovs: Option<&'a AovImage>,
+    pub round: usize,
+    pub passes_done: usize,
+
+    // Whether this is the last round, which it is once every pixel has had enough passes
+    pub finished: bool,
+    sample_stats: &'a [SampleStats],
+    settings: &'a RenderSettings,
+    scene_hash: u64,
+}
+
+impl<'a> RenderProgress<'a> {
+    // Take a copy of where the render has got to, which resume can carry on from. A round that
+    // was cut short by cancelling the render has only traced some of its samples, and carrying on
+    // from there wouldn't give the same image, so there is no checkpoint for one of those.
+    pub fn checkpoint(&self, scene_name: &str) -> Option<RenderCheckpoint> {
+        if self.settings.cancellation.is_cancelled() {
+            return None;
+        }
+
+        let mut image = VectorImage::new(self.image.width(), self.image.height());
+        image
+            .pixels_mut()
+            .zip(self.image.pixels())
+            .for_each(|(dst, src)| *dst = *src);
+
+        Some(RenderCheckpoint {
+            scene_name: scene_name.to_string(),
+            scene_hash: self.scene_hash,
+            settings: self.settings.clone(),
+            round: self.round + 1,
+            passes_done: self.passes_done,
+            image,
+            sample_stats: self.sample_stats.into(),
+            aovs: self.aovs.cloned(),
+        })
+    }
+}
+
+// The beauty image, along with the auxiliary outputs if the settings asked for them
+pub struct RenderOutput {
+    pub image: VectorImage,
+    pub aovs: Option<AovImage>,
+    pub(crate) sample_stats: Box<[SampleStats]>,
+}
+
+impl RenderOutput {
+    // Just the given part of the render, as though that was all there was
+    pub fn crop(&self, window: CropWindow) -> RenderOutput {
+        let window = window.clip(self.image.width(), self.image.height());
+        RenderOutput {
+            image: self.image.crop(window),
+            aovs: self.aovs.as_ref().map(|aovs| aovs.crop(window)),
+            sample_stats: window.crop(&self.sample_stats, self.image.width()),
+        }
+    }
+}
+
+// Adding renders of the same scene together gives one with all of their samples. The AOVs only
+// survive if both of them have some.
+impl std::ops::Add for RenderOutput {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            image: self.image + other.image,
+            aovs: self.aovs.zip(other.aovs).map(|(a, b)| a + b),
+            sample_stats: self
+                .sample_stats
+                .iter()
+                .zip(other.sample_stats.iter())
+                .map(|(a, b)| *a + *b)
+                .collect(),
+        }
+    }
+}
+
 pub async fn scan<StatsAccumulator: 'static + RenderStatsAccumulator + Sync + Send>(
+    scene: Scene,
+    image_size: (usize, usize),
+    t0: FloatType,
+    t1: FloatType,
+    settings: RenderSettings,
+    stats: Arc<RwLock<StatsAccumulator>>,
+) -> RenderOutput {
+    scan_progressive(scene, image_size, t0, t1, settings, stats, |_| {}).await
+}
+
+// Where a render starts from, which is either nothing at all or a checkpoint
+struct ScanState {
+    accumulator: Accumulator,
+    round: usize,
+    passes_done: usize,
+}
+
+pub async fn scan_progressive<StatsAccumulator: 'static + RenderStatsAccumulator + Sync + Send>(
     scene: Scene,
     (image_width, image_height): (usize, usize),
     t0: FloatType,
     t1: FloatType,
-    thread_count: usize,
-    min_passes: usize,
+    settings: RenderSettings,
+    stats: Arc<RwLock<StatsAccumulator>>,
+    on_progress: impl FnMut(RenderProgress),
+) -> RenderOutput {
+    let state = ScanState {
+        accumulator: Accumulator::new(image_width, image_height, settings.aovs),
+        round: 0,
+        passes_done: 0,
+    };
+    scan_from(scene, t0, t1, settings, state, stats, on_progress).await
+}
+
+// Carry on with a render from a checkpoint. The settings normally come from the checkpoint too,
+// and as long as they stay the same the result is exactly what the render would have produced
+// had it never stopped. Raising the pass counts is fine for keeping a render going for longer.
+pub async fn scan_resume<StatsAccumulator: 'static + RenderStatsAccumulator + Sync + Send>(
+    scene: Scene,
+    checkpoint: RenderCheckpoint,
+    t0: FloatType,
+    t1: FloatType,
+    settings: RenderSettings,
     stats: Arc<RwLock<StatsAccumulator>>,
-) -> VectorImage {
-    let thread_count = thread_count.max(1);
-    let min_passes = min_passes.max(1);
-    let passes_per_thread = (min_passes + thread_count - 1) / thread_count;
+    on_progress: impl FnMut(RenderProgress),
+) -> RenderOutput {
+    // Turning the auxiliary outputs on part way through means they only cover the later passes
+    let (width, height) = checkpoint.image_size();
+    let aovs = match (settings.aovs, checkpoint.aovs) {
+        (true, Some(aovs)) => Some(aovs),
+        (true, None) => Some(AovImage::new(width, height)),
+        (false, _) => None,
+    };
+
+    let state = ScanState {
+        accumulator: Accumulator {
+            image: checkpoint.image,
+            sample_stats: checkpoint.sample_stats,
+            aovs,
+        },
+        round: checkpoint.round,
+        passes_done: checkpoint.passes_done,
+    };
+    scan_from(scene, t0, t1, settings, state, stats, on_progress).await
+}
 
+async fn scan_from<StatsAccumulator: 'static + RenderStatsAccumulator + Sync + Send>(
+    scene: Scene,
+    t0: FloatType,
+    t1: FloatType,
+    settings: RenderSettings,
+    state: ScanState,
+    stats: Arc<RwLock<StatsAccumulator>>,
+    mut on_progress: impl FnMut(RenderProgress),
+) -> RenderOutput {
+    let min_passes = settings.round_passes();
+    let passes_per_round = settings.passes_per_round();
+    let thread_count = settings.thread_count();
+
+    let (image_width, image_height) = (
+        state.accumulator.image.width(),
+        state.accumulator.image.height(),
+    );
+    let scene_hash = scene.fingerprint(t0, t1);
     let scene = Arc::new(PreparedScene::make(scene, t0, t1));
+    let settings = Arc::new(settings);
+    let accumulator = Arc::new(Mutex::new(state.accumulator));
+    // Cropping keeps the tiles where they would have been, so the pixels in the crop come out
+    // just as they would in a render of the whole image, as long as no filter spreads samples
+    // into them from outside
+    let bounds = settings
+        .crop
+        .unwrap_or_else(|| CropWindow::new(0, 0, image_width, image_height))
+        .clip(image_width, image_height);
+    let bounds_tile = Tile {
+        x: bounds.x,
+        y: bounds.y,
+        width: bounds.width,
+        height: bounds.height,
+    };
+    let tiles: Vec<_> = Tile::split(image_width, image_height, TILE_SIZE)
+        .iter()
+        .filter_map(|tile| tile.intersect(&bounds_tile))
+        .collect();
+
+    // Every pixel gets the minimum number of passes, a round at a time. After that, if we're
+    // sampling adaptively, we keep going with only the pixels that are still too noisy. Which
+    // pixels those are only depends on what has been accumulated, so it is worked out afresh
+    // before every round, which also covers carrying on from a checkpoint. There is no next round
+    // once we're done, and otherwise it either covers every pixel or just the noisy ones.
+    let next_round = |passes_done: usize| -> Option<Option<Arc<[bool]>>> {
+        if passes_done < min_passes {
+            return Some(None);
+        }
+
+        let noise_threshold = match settings.noise_threshold {
+            Some(noise_threshold) if passes_done < settings.max_passes() => noise_threshold,
+            _ => return None,
+        };
+
+        let noisy: Arc<[bool]> = accumulator
+            .lock()
+            .unwrap()
+            .relative_errors()
+            .enumerate()
+            .map(|(idx, error)| {
+                error > noise_threshold && bounds.contains(idx % image_width, idx / image_width)
+            })
+            .collect();
+        if noisy.contains(&true) {
+            Some(Some(noisy))
+        } else {
+            None
+        }
+    };
+
+    let mut passes_done = state.passes_done;
+    let mut next = next_round(passes_done);
+
+    for round in state.round.. {
+        let active = match next {
+            Some(active) => active,
+            None => break,
+        };
+
+        // Outlier rejection compares samples against what their pixel had before the round, as
+        // well as what it has had so far in its part of the round, which keeps it the same
+        // whichever order the tiles are traced in
+        let history = settings
+            .outlier_sigma
+            .map(|_| accumulator.lock().unwrap().sample_stats.clone().into());
+
+        let round_passes = if passes_done < min_passes {
+            passes_per_round.min(min_passes - passes_done)
+        } else {
+            passes_per_round.min(settings.max_passes() - passes_done)
+        };
+
+        // Each tile's passes are split up between the threads, so that no one thread is stuck with
+        // a slow tile while the others sit idle. The parts are put back together in pass order,
+        // so the samples for a pixel are always added up the same way whichever threads traced
+        // them, and the same seed gives exactly the same image. Whatever a filter spreads into
+        // other tiles is added at the end of the round, in tile order, for the same reason.
+        let part_passes = (round_passes + thread_count - 1) / thread_count;
+        let work = tiles
+            .iter()
+            .filter(|tile| match &active {
+                Some(active) => tile.pixels().any(|(x, y)| active[(y * image_width) + x]),
+                None => true,
+            })
+            .flat_map(|tile| {
+                (0..round_passes)
+                    .step_by(part_passes)
+                    .map(move |pass| TileWork {
+                        tile: *tile,
+                        first_pass: passes_done + pass,
+                        passes: part_passes.min(round_passes - pass),
+                    })
+            })
+            .collect();
+
+        scan_round(
+            (image_width, image_height),
+            work,
+            RoundPixels { active, history },
+            &scene,
+            &settings,
+            &stats,
+            &accumulator,
+        )
+        .await;
+        passes_done += round_passes;
+        next = next_round(passes_done);
+
+        {
+            let accumulator = accumulator.lock().unwrap();
+            on_progress(RenderProgress {
+                image: &accumulator.image,
+                aovs: accumulator.aovs.as_ref(),
+                round,
+                passes_done,
+                finished: next.is_none(),
+                sample_stats: &accumulator.sample_stats,
+                settings: &settings,
+                scene_hash,
+            });
+        }
+
+        if settings.cancellation.is_cancelled() {
+            break;
+        }
+    }
+
+    let accumulator = Arc::try_unwrap(accumulator)
+        .ok()
+        .expect("all workers have finished")
+        .into_inner()
+        .unwrap();
+
+    RenderOutput {
+        image: accumulator.image,
+        aovs: accumulator.aovs,
+        sample_stats: accumulator.sample_stats,
+    }
+}
+
+async fn scan_round<StatsAccumulator: 'static + RenderStatsAccumulator + Sync + Send>(
+    (image_width, image_height): (usize, usize),
+    work: Vec<TileWork>,
+    round_pixels: RoundPixels,
+    scene: &Arc<PreparedScene>,
+    settings: &Arc<RenderSettings>,
+    stats: &Arc<RwLock<StatsAccumulator>>,
+    accumulator: &Arc<Mutex<Accumulator>>,
+) {
+    let thread_count = settings.thread_count();
+    let pending = Arc::new(Mutex::new(PendingTiles::new(&work)));
+    let scheduler = Arc::new(TileScheduler::new(thread_count, work));
 
-    let futures = (0..thread_count).into_iter().map(|_| {
+    let futures = (0..thread_count).into_iter().map(|worker| {
+        let mut spills = Vec::new();
         let thread_scene = scene.clone();
         let thread_stats = stats.clone();
+        let thread_settings = settings.clone();
+        let thread_scheduler = scheduler.clone();
+        let thread_accumulator = accumulator.clone();
+        let thread_pending = pending.clone();
+        let thread_round_pixels = round_pixels.clone();
         tokio::task::spawn_blocking(move || {
-            scan_batch(
-                image_width,
-                image_height,
-                passes_per_thread,
-                &thread_scene,
-                thread_stats.as_ref(),
-            )
+            while let Some(work) = thread_scheduler
+                .next(worker)
+                .filter(|_| !thread_settings.cancellation.is_cancelled())
+            {
+                let tile_data = scan_tile(
+                    (image_width, image_height),
+                    work,
+                    &thread_round_pixels,
+                    &thread_scene,
+                    &thread_settings,
+                    thread_stats.as_ref(),
+                );
+                let tile_data = match thread_pending.lock().unwrap().add(&work, tile_data) {
+                    Some(tile_data) => tile_data,
+                    None => continue,
+                };
+
+                thread_accumulator
+                    .lock()
+                    .unwrap()
+                    .add_tile(&work.tile, &tile_data);
+                if !tile_data.spill.is_empty() {
+                    spills.push((work.tile, tile_data.spill));
+                }
+            }
+            spills
         })
     });
 
-    let mut thread_results = join_all(futures).await.into_iter();
-    let result = thread_results.next();
-    result
-        .map(|result| {
-            thread_results.fold(result, |l, r| match (l, r) {
-                (Ok(l), Ok(r)) => Ok(l + r),
-                (Err(e), _) => Err(e),
-                (_, Err(e)) => Err(e),
-            })
+    let mut spills = Vec::new();
+    for result in join_all(futures).await {
+        spills.extend(result.unwrap());
+    }
+
+    // Cancelling the render can leave tiles with only some of their parts traced
+    let mut accumulator = accumulator.lock().unwrap();
+    let pending = std::mem::take(&mut *pending.lock().unwrap());
+    for (tile, tile_data) in pending.into_partial() {
+        accumulator.add_tile(&tile, &tile_data);
+        if !tile_data.spill.is_empty() {
+            spills.push((tile, tile_data.spill));
+        }
+    }
+
+    spills.sort_by_key(|(tile, _)| (tile.y, tile.x));
+    for (_, spill) in spills.iter() {
+        accumulator.add_spill(spill);
+    }
+}
+
+// The parts of a tile that have been traced so far in a round, each kept until the rest of the
+// tile's passes are in
+struct PendingTile {
+    tile: Tile,
+    part_count: usize,
+    parts: Vec<(usize, TileSamples)>,
+}
+
+#[derive(Default)]
+struct PendingTiles {
+    tiles: HashMap<(usize, usize), PendingTile>,
+}
+
+impl PendingTiles {
+    fn new(work: &[TileWork]) -> Self {
+        let mut tiles = HashMap::new();
+        for work in work {
+            tiles
+                .entry((work.tile.x, work.tile.y))
+                .or_insert_with(|| PendingTile {
+                    tile: work.tile,
+                    part_count: 0,
+                    parts: Vec::new(),
+                })
+                .part_count += 1;
+        }
+        Self { tiles }
+    }
+
+    // Hands back the whole tile, once this was the last part of it
+    fn add(&mut self, work: &TileWork, tile_data: TileSamples) -> Option<TileSamples> {
+        let key = (work.tile.x, work.tile.y);
+        let pending = self.tiles.get_mut(&key).unwrap();
+        pending.parts.push((work.first_pass, tile_data));
+        if pending.parts.len() < pending.part_count {
+            return None;
+        }
+
+        self.tiles
+            .remove(&key)
+            .and_then(|pending| TileSamples::merge(pending.parts))
+    }
+
+    // Whatever there is of the tiles that never got all of their parts
+    fn into_partial(self) -> impl Iterator<Item = (Tile, TileSamples)> {
+        self.tiles.into_values().filter_map(|pending| {
+            let tile = pending.tile;
+            TileSamples::merge(pending.parts).map(|tile_data| (tile, tile_data))
         })
-        .transpose()
-        .unwrap()
-        .unwrap()
+    }
+}
+
+// What is known about each pixel at the start of a round. Only the active pixels get traced,
+// if there is a mask, and the history is what each pixel had accumulated before the round.
+#[derive(Clone)]
+struct RoundPixels {
+    active: Option<Arc<[bool]>>,
+    history: Option<Arc<[SampleStats]>>,
 }
 
-fn scan_batch(
-    image_width: usize,
-    image_height: usize,
-    pass_count: usize,
+// Pixels need this many samples before any of their samples can be judged to be outliers
+const MIN_OUTLIER_SAMPLES: FloatType = 8.0;
+
+// The brightest a sample can be, going by the samples that its pixel already has, before it
+// counts as an outlier
+fn outlier_limit(sample_stats: SampleStats, outlier_sigma: FloatType) -> Option<FloatType> {
+    let count = sample_stats.count;
+    if count < MIN_OUTLIER_SAMPLES {
+        return None;
+    }
+
+    let mean = sample_stats.luminance / count;
+    let variance = ((sample_stats.luminance_squares / count) - (mean * mean)).max(0.0);
+    Some(mean + (outlier_sigma * variance.sqrt()))
+}
+
+fn scan_tile(
+    (image_width, image_height): (usize, usize),
+    work: TileWork,
+    round_pixels: &RoundPixels,
     scene: &PreparedScene,
+    settings: &RenderSettings,
     stats: &RwLock<impl RenderStatsAccumulator>,
-) -> VectorImage {
-    let mut image = VectorImage::new(image_width, image_height);
+) -> TileSamples {
+    let width = image_width;
+    let bounds = settings
+        .crop
+        .unwrap_or_else(|| CropWindow::new(0, 0, image_width, image_height))
+        .clip(image_width, image_height);
     let (image_width, image_height) = (image_width as FloatType, image_height as FloatType);
+    let first_pass = work.first_pass as u64;
+    let sample_count = settings.round_passes() as u64;
     let mut pixel_stats = TracingStats::new();
 
-    for (x, y, pixel) in image.enumerate_pixels_mut() {
-        *pixel = (0..pass_count)
-            .into_iter()
-            .map(|_s| {
-                let (s, t) = (
-                    ((x as FloatType) + random_in_range(-0.5, 0.5)) / image_width,
-                    ((image_height - 1.0 - (y as FloatType)) + random_in_range(-0.5, 0.5))
-                        / image_height,
-                );
-                let ray = scene.camera().make_ray(s, t);
+    let pixels: Vec<_> = work
+        .tile
+        .pixels()
+        .filter(|(x, y)| match &round_pixels.active {
+            Some(active) => active[(y * width) + x],
+            None => true,
+        })
+        .collect();
+
+    // The sums cover the tile and however far around it the filter reaches
+    let filter = settings.filter;
+    let margin = filter.margin();
+    let (sums_width, sums_height) = (
+        work.tile.width + (2 * margin),
+        work.tile.height + (2 * margin),
+    );
+    let mut tile_data = vec![cgmath::vec4(0.0, 0.0, 0.0, 0.0); sums_width * sums_height];
+    let mut sample_stats = vec![SampleStats::default(); work.tile.pixel_count()];
+    let mut weights = vec![(0.0, 0.0); (2 * margin) + 1];
+
+    let mut tile_aovs = if settings.aovs {
+        Some(vec![AovPixel::default(); work.tile.pixel_count()])
+    } else {
+        None
+    };
+
+    // Go over the whole tile once per pass, rather than finishing each pixel before moving on,
+    // so that if we're cancelled part way through the whole tile has something in it
+    for pass in 0..work.passes {
+        if settings.cancellation.is_cancelled() {
+            break;
+        }
 
-                let ret = cgmath::Vector4::from(trace(&ray, scene));
+        for (x, y) in pixels.iter().copied() {
+            let pixel_index = ((y * width) + x) as u64;
+            let mut sampler = settings.sampler.sampler(
+                settings.seed,
+                pixel_index,
+                first_pass + pass as u64,
+                sample_count,
+            );
 
-                pixel_stats.count_pixel();
+            let offset = sampler.next_2d();
+            let (s, t) = (
+                ((x as FloatType) + offset.x - 0.5) / image_width,
+                ((image_height - 1.0 - (y as FloatType)) + offset.y - 0.5) / image_height,
+            );
+            let ray = scene.camera().make_ray(s, t, sampler.as_mut());
 
-                if let Ok(mut lock) = stats.try_write() {
-                    let next_stats = std::mem::replace(&mut pixel_stats, TracingStats::new());
-                    lock.add_stats(next_stats.into());
+            let (tile_x, tile_y) = (x - work.tile.x, y - work.tile.y);
+            let tile_index = (tile_y * work.tile.width) + tile_x;
+
+            // Samples the camera can't see are black, but still count towards the pixel
+            let (mut sample, aov_sample) = match &ray {
+                Some(ray) => {
+                    let (color, aov_sample) = trace(ray, scene, settings, sampler.as_mut());
+                    (cgmath::Vector4::from(color), Some(aov_sample))
                 }
+                None => (cgmath::vec4(0.0, 0.0, 0.0, 1.0), None),
+            };
+            if let (Some(tile_aovs), Some(aov_sample)) = (&mut tile_aovs, &aov_sample) {
+                tile_aovs[tile_index].add_sample(aov_sample);
+            }
 
-                ret
-            })
-            .fold(cgmath::vec4(0.0, 0.0, 0.0, 0.0), |sum, v| sum + v);
+            let mut sample_luminance = luminance(sample.truncate());
+
+            if let (Some(outlier_sigma), Some(history)) =
+                (settings.outlier_sigma, &round_pixels.history)
+            {
+                let so_far = history[pixel_index as usize] + sample_stats[tile_index];
+                if let Some(limit) = outlier_limit(so_far, outlier_sigma) {
+                    if sample_luminance > limit {
+                        sample = (sample.truncate() * (limit / sample_luminance)).extend(1.0);
+                        sample_luminance = limit;
+                    }
+                }
+            }
+
+            // The filter is symmetrical, so it doesn't matter that rows go down the image while
+            // the offset goes up it
+            for (idx, weight) in weights.iter_mut().enumerate() {
+                let pixels_away = (idx as FloatType) - (margin as FloatType);
+                *weight = (
+                    filter.weight(offset.x - 0.5 - pixels_away),
+                    filter.weight(offset.y - 0.5 + pixels_away),
+                );
+            }
+
+            for (dy, (_, y_weight)) in weights.iter().enumerate() {
+                for (dx, (x_weight, _)) in weights.iter().enumerate() {
+                    let weight = x_weight * y_weight;
+                    if weight != 0.0 {
+                        tile_data[((tile_y + dy) * sums_width) + tile_x + dx] += sample * weight;
+                    }
+                }
+            }
+            sample_stats[tile_index].add_sample(sample_luminance);
+
+            pixel_stats.count_pixel();
+
+            if let Ok(mut lock) = stats.try_write() {
+                let next_stats = std::mem::replace(&mut pixel_stats, TracingStats::new());
+                lock.add_stats(next_stats.into());
+            }
+        }
+    }
+
+    // Split off whatever landed outside the tile, leaving out anything that is off the image, or
+    // outside the crop
+    let mut sums = Vec::with_capacity(work.tile.pixel_count());
+    let mut spill = Vec::new();
+    for (idx, sum) in tile_data.into_iter().enumerate() {
+        let (sums_x, sums_y) = (idx % sums_width, idx / sums_width);
+        let inside = |value: usize, size: usize| value >= margin && value < size + margin;
+        if inside(sums_x, work.tile.width) && inside(sums_y, work.tile.height) {
+            sums.push(sum);
+            continue;
+        }
+
+        let (x, y) = (work.tile.x + sums_x, work.tile.y + sums_y);
+        if x >= margin && y >= margin && bounds.contains(x - margin, y - margin) {
+            spill.push((((y - margin) * width) + x - margin, sum));
+        }
     }
 
-    image
+    TileSamples {
+        sums,
+        sample_stats,
+        spill,
+        aovs: tile_aovs,
+    }
 }
 
-const MAX_DEPTH: usize = 50;
+// Both the material and the emitters get a chance to pick the direction that light arrives from.
+// We weight each of them using the power heuristic, so whichever strategy was more likely to pick
+// a given direction dominates.
+fn power_heuristic(pdf: FloatType, other_pdf: FloatType) -> FloatType {
+    let pdf2 = pdf * pdf;
+    let other_pdf2 = other_pdf * other_pdf;
 
-#[derive(Debug, Clone, Copy)]
-struct ScatterStackRecord {
-    partial: PartialScatterResult,
-    emitted: Color,
+    if pdf2 + other_pdf2 > 0.0 {
+        pdf2 / (pdf2 + other_pdf2)
+    } else {
+        0.0
+    }
 }
 
-type FixedSizeAttenuationStack<'a> =
-    crate::fixed_size_stack::FixedSizeStack<'a, ScatterStackRecord>;
+// Cast a shadow ray towards one of the emitters and work out how much light it contributes
+// to the scattered light at this hit
+fn sample_direct_light(
+    ray_in: &Ray,
+    hit_result: GeometryHitResult,
+    material: &dyn BaseMaterial,
+    scene: &PreparedScene,
+    sampler: &mut dyn Sampler,
+) -> Vector3 {
+    let hit_point = hit_result.hit_point();
 
-fn collapse_color_stack(mut stack: FixedSizeAttenuationStack<'_>, input_color: Color) -> Color {
-    let mut color = input_color;
+    scene
+        .sample_emitter(hit_point, ray_in.time(), sampler)
+        .and_then(|direction| {
+            let partial = material.base_scatter_towards(ray_in, hit_result, direction)?;
+            let shadow_ray = Ray::new(hit_point, direction, ray_in.time());
 
-    while let Some(scatter_record) = stack.pop() {
-        let calc_color = Vector4::from(scatter_record.emitted)
-            + scatter_record
-                .partial
-                .attenuation
-                .extend(1.0)
-                .mul_element_wise(Vector4::from(color));
+            // The first thing the shadow ray hits has to be an emitter, otherwise it is in shadow
+            let (light_hit, light_material) = scene
+                .intersect(&shadow_ray, 0.001, constants::INFINITY, sampler)?
+                .split();
+            if !light_material.base_is_emissive() {
+                return None;
+            }
 
-        color = calc_color.try_into().unwrap();
-    }
+            let pdf = scene.emitter_pdf(&shadow_ray, sampler);
+            if pdf <= 0.0 {
+                return None;
+            }
 
-    // We need to ensure that the alpha channel is 1 when we come out of here, because that is used
-    // later to average the samples.
-    Vector4::from(color)
-        .truncate()
-        .extend(1.0)
-        .try_into()
-        .unwrap()
+            let weight = power_heuristic(pdf, partial.pdf.unwrap_or(0.0));
+            let emitted = Vector3::from(light_material.base_emitted(&shadow_ray, light_hit));
+            Some(partial.attenuation.mul_element_wise(emitted) * (weight / pdf))
+        })
+        .unwrap_or_else(Vector3::zero)
 }
 
-pub fn trace(ray: &Ray, scene: &PreparedScene) -> Color {
-    let mut attenuation_stack_data: [_; MAX_DEPTH] = MaybeUninit::uninit_array();
-    let mut attenuation_stack = FixedSizeAttenuationStack::new(&mut attenuation_stack_data);
+// Trace a camera ray, giving back the light it brings in along with what it hit first, which is
+// only filled in when the render wants AOVs
+pub fn trace(
+    ray: &Ray,
+    scene: &PreparedScene,
+    settings: &RenderSettings,
+    sampler: &mut dyn Sampler,
+) -> (Color, AovSample) {
+    // Rather than unwinding a stack of attenuations at the end of the path, we keep track of how
+    // much of the light arriving at the current hit makes it back to the camera, and add each bit
+    // of light as we find it.
+    let mut radiance = Vector3::zero();
+    let mut throughput = vec3(1.0, 1.0, 1.0);
+
+    // Whatever the camera ray sees directly, which the luminance clamp leaves alone
+    let mut seen_directly = Vector3::zero();
 
     let mut current_ray = *ray;
+    let mut aov_sample = AovSample {
+        time: ray.time(),
+        hit: None,
+    };
 
-    loop {
-        if let Some(hit_result) = scene.intersect(&current_ray, 0.001, constants::INFINITY) {
-            let (hit_result, material) = hit_result.split();
-            let (emitted, scatter) = material.base_scatter(&current_ray, hit_result).split();
+    // The density that the last hit scattered the current ray with. If there is one then we also
+    // sampled the emitters directly at that hit, so light from any emitter that the ray finds
+    // has to be weighted against that.
+    let mut scatter_pdf: Option<FloatType> = None;
 
-            if let Some(ScatterResult { partial, scattered }) = scatter {
-                if !attenuation_stack.try_push(ScatterStackRecord { partial, emitted }) {
-                    // We cannot recurse any further, so stop here and return black
-                    return collapse_color_stack(attenuation_stack, constants::BLACK);
+    for depth in 0.. {
+        let (shape, hit_result) =
+            match scene.intersect_shape(&current_ray, 0.001, constants::INFINITY, sampler) {
+                Some(hit) => hit,
+                None => {
+                    // We did not intersect with any objects, so sample the sky
+                    let sky = Vector3::from(scene.sky().sample(&current_ray));
+                    radiance += throughput.mul_element_wise(sky);
+                    if depth == 0 {
+                        seen_directly = radiance;
+                    }
+                    break;
                 }
+            };
 
-                current_ray = scattered;
-            } else {
-                return collapse_color_stack(attenuation_stack, emitted);
+        let (hit_result, material) = hit_result.split();
+        if depth == 0 && settings.aovs {
+            aov_sample.hit = Some(AovHit::new(shape, &hit_result, material.as_ref()));
+        }
+
+        let (emitted, scatter) = material
+            .base_scatter(&current_ray, hit_result.clone(), sampler)
+            .split();
+
+        let emitted = match scatter_pdf {
+            Some(pdf) if material.base_is_emissive() => emitted.attenuate(power_heuristic(
+                pdf,
+                scene.emitter_pdf(&current_ray, sampler),
+            )),
+            _ => emitted,
+        };
+        radiance += throughput.mul_element_wise(Vector3::from(emitted));
+        if depth == 0 {
+            seen_directly = radiance;
+        }
+
+        let ScatterResult { partial, scattered } = match scatter {
+            Some(scatter) if depth < settings.max_depth => scatter,
+            _ => break,
+        };
+
+        // We can only sample lights from materials that scatter over a range of
+        // directions. Mirrors and glass would never see the light we picked.
+        scatter_pdf = partial.pdf;
+        if scatter_pdf.is_some() {
+            let direct =
+                sample_direct_light(&current_ray, hit_result, material.as_ref(), scene, sampler);
+            radiance += throughput.mul_element_wise(direct);
+        }
+
+        throughput.mul_assign_element_wise(partial.attenuation);
+
+        let survival_probability = settings.survival_probability(depth, throughput);
+        if survival_probability < 1.0 {
+            if sampler.next_1d() >= survival_probability {
+                break;
             }
-        } else {
-            // We did not intersect with any objects, so sample the sky
-            return collapse_color_stack(attenuation_stack, scene.sky().sample(&current_ray));
+
+            throughput /= survival_probability;
+        }
+
+        current_ray = scattered;
+    }
+
+    // Exposure goes on before the clamp, so that the clamp and outlier rejection both judge
+    // samples by how bright they come out in the image
+    let exposure = scene.camera().exposure();
+    let (mut radiance, seen_directly) = (radiance * exposure, seen_directly * exposure);
+    if let Some(max_sample_luminance) = settings.max_sample_luminance {
+        let bounced = radiance - seen_directly;
+        let bounced_luminance = luminance(bounced);
+        if bounced_luminance > max_sample_luminance {
+            radiance = seen_directly + (bounced * (max_sample_luminance / bounced_luminance));
         }
     }
+
+    // We need to ensure that the alpha channel is 1 when we come out of here, because that is used
+    // later to average the samples.
+    (radiance.extend(1.0).try_into().unwrap(), aov_sample)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use crate::{Camera, CompoundVisible, FilterKind, PixelFilter, Skinnable};
+
+    fn sphere_scene() -> Scene {
+        let mut shapes = CompoundVisible::default();
+        shapes.push(
+            sphere(Point3::new(0.0, 0.0, -3.0), 1.0)
+                .apply_material(lambertian(solid_texture(constants::WHITE))),
+        );
+        let camera = Camera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            vec3(0.0, 1.0, 0.0),
+            Deg(60.0).into(),
+            1.0,
+            0.0,
+            1.0,
+        );
+        Scene::new(camera, regular_sky(), shapes)
+    }
+
+    fn render(settings: &RenderSettings) -> RenderOutput {
+        let stats = Arc::new(RwLock::new(TracingStats::new()));
+        tokio::runtime::Runtime::new().unwrap().block_on(scan(
+            sphere_scene(),
+            (8, 8),
+            0.0,
+            1.0,
+            settings.clone(),
+            stats,
+        ))
+    }
+
+    fn pixels(image: &VectorImage) -> Vec<Vector4> {
+        image.pixels().copied().collect()
+    }
+
+    #[test]
+    fn test_sample_stats_with_filter() {
+        // Mitchell weights go below zero, so the image sums are nothing like the number of
+        // samples, but each sample still counts once towards the pixel it was taken in
+        let mut settings = RenderSettings {
+            filter: PixelFilter::new(FilterKind::Mitchell),
+            ..RenderSettings::new(2, 8)
+        };
+        let output = render(&settings);
+        assert!(output.image.pixels().any(|pixel| pixel.w != 8.0));
+        for sample_stats in output.sample_stats.iter() {
+            assert_eq!(sample_stats.count, 8.0);
+            assert!(sample_stats.variance().unwrap() >= 0.0);
+        }
+
+        // Adaptive sampling adds a round's passes at a time to the pixels that are still noisy,
+        // and with a threshold this low that is all of them
+        settings.noise_threshold = Some(0.0001);
+        settings.max_passes = 24;
+        let output = render(&settings);
+        for sample_stats in output.sample_stats.iter() {
+            assert_eq!(sample_stats.count, 24.0);
+        }
+    }
+    #[test]
+    fn test_renders_are_repeatable() {
+        // The passes of the one tile are split between the threads, and however the threads
+        // happen to run the parts are added up in the same order
+        let settings = RenderSettings {
+            seed: 7,
+            ..RenderSettings::new(3, 12)
+        };
+        let first = render(&settings);
+        let second = render(&settings);
+        assert_eq!(pixels(&first.image), pixels(&second.image));
+        assert_eq!(first.sample_stats, second.sample_stats);
+    }
+
+    #[test]
+    fn test_resume_matches_uninterrupted_render() {
+        let settings = || RenderSettings {
+            passes_per_round: Some(2),
+            ..RenderSettings::new(2, 6)
+        };
+
+        // Stop after the first round, keeping a checkpoint of it
+        let stopped = settings();
+        let cancellation = stopped.cancellation.clone();
+        let mut checkpoint = None;
+        let stats = Arc::new(RwLock::new(TracingStats::new()));
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(scan_progressive(
+                sphere_scene(),
+                (8, 8),
+                0.0,
+                1.0,
+                stopped,
+                stats.clone(),
+                |progress| {
+                    if checkpoint.is_none() {
+                        checkpoint = progress.checkpoint("sphere");
+                        cancellation.cancel();
+                    }
+                },
+            ));
+        let checkpoint = checkpoint.unwrap();
+        assert_eq!(checkpoint.passes_done, 2);
+
+        let resumed = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(scan_resume(
+                sphere_scene(),
+                checkpoint,
+                0.0,
+                1.0,
+                settings(),
+                stats,
+                |_| {},
+            ));
+        let uninterrupted = render(&settings());
+        assert_eq!(pixels(&resumed.image), pixels(&uninterrupted.image));
+        assert_eq!(resumed.sample_stats, uninterrupted.sample_stats);
+    }
 }
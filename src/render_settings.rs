@@ -0,0 +1,221 @@
+use crate::{math::*, CancellationToken, PixelFilter, SamplerKind};
+
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    pub thread_count: usize,
+    pub min_passes: usize,
+
+    // With a noise threshold, pixels carry on being sampled after min_passes until the standard
+    // error of their mean, relative to their brightness, drops below it or they reach max_passes.
+    pub noise_threshold: Option<FloatType>,
+    pub max_passes: usize,
+
+    // Passes are traced in rounds of this many, and progress is reported after each one. With
+    // none, each round has one pass for every thread.
+    pub passes_per_round: Option<usize>,
+
+    // Paths are never longer than max_depth bounces. Once a path is min_depth bounces long it
+    // becomes a candidate for russian roulette, if that is enabled.
+    pub max_depth: usize,
+    pub min_depth: usize,
+    pub russian_roulette: bool,
+
+    // Fireflies come from rare paths that carry far more light than their neighbours, and take a
+    // very long time to average out. Clamping stops any sample being brighter than
+    // max_sample_luminance, not counting light that the camera sees directly, so lights keep their
+    // brightness. Outlier rejection brings any sample more than outlier_sigma standard deviations
+    // brighter than the rest of its pixel back down to that. Both judge samples after the camera's
+    // exposure has been applied, and both make the image darker than it should be, in exchange
+    // for it being far less noisy.
+    pub max_sample_luminance: Option<FloatType>,
+    pub outlier_sigma: Option<FloatType>,
+
+    // Every sample is traced with random numbers that come from this, so renders with the same
+    // seed come out the same
+    pub seed: u64,
+    pub sampler: SamplerKind,
+
+    // How samples are spread over the pixels around where they were taken
+    pub filter: PixelFilter,
+
+    // Only trace the pixels in this part of the image. The rest are left without any samples,
+    // so they come out black, and filters don't spread anything into them.
+    pub crop: Option<CropWindow>,
+
+    // Also record what the first thing each camera ray hits looks like, for denoising and
+    // compositing. See AovImage for what gets recorded.
+    pub aovs: bool,
+
+    // Cancelling this stops the render early. Whatever has been accumulated by then is returned
+    // as normal, although some pixels will have fewer samples than others.
+    pub cancellation: CancellationToken,
+}
+
+impl RenderSettings {
+    pub fn new(thread_count: usize, min_passes: usize) -> Self {
+        Self {
+            thread_count,
+            min_passes,
+            noise_threshold: None,
+            max_passes: min_passes,
+            passes_per_round: None,
+            max_depth: 50,
+            min_depth: 3,
+            russian_roulette: true,
+            max_sample_luminance: None,
+            outlier_sigma: None,
+            seed: 0,
+            aovs: false,
+            sampler: SamplerKind::Sobol,
+            filter: PixelFilter::default(),
+            crop: None,
+            cancellation: CancellationToken::new(),
+        }
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.thread_count.max(1)
+    }
+
+    pub fn min_passes(&self) -> usize {
+        self.min_passes.max(1)
+    }
+
+    // The number of passes every pixel gets. This is the minimum, rounded up so that it splits
+    // evenly between the threads.
+    pub fn round_passes(&self) -> usize {
+        let thread_count = self.thread_count();
+        ((self.min_passes() + thread_count - 1) / thread_count) * thread_count
+    }
+
+    pub fn passes_per_round(&self) -> usize {
+        self.passes_per_round
+            .unwrap_or_else(|| self.thread_count())
+            .max(1)
+    }
+
+    pub fn max_passes(&self) -> usize {
+        self.max_passes.max(self.min_passes())
+    }
+
+    // The chance that a path with the given throughput carries on after the current bounce. Dim
+    // paths are likely to be stopped, and the ones that survive are brightened to make up for it.
+    pub fn survival_probability(&self, depth: usize, throughput: Vector3) -> FloatType {
+        if !self.russian_roulette || depth < self.min_depth {
+            1.0
+        } else {
+            throughput.x.max(throughput.y).max(throughput.z).min(0.95)
+        }
+    }
+}
+
+// A rectangle of pixels, measured from the top left of the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CropWindow {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl CropWindow {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    // The same, but with everything as a fraction of the size of the image. Pixels that are
+    // partly inside the window are included.
+    pub fn normalized(
+        (x, y, width, height): (FloatType, FloatType, FloatType, FloatType),
+        image_width: usize,
+        image_height: usize,
+    ) -> Self {
+        let (image_width, image_height) = (image_width as FloatType, image_height as FloatType);
+        let x0 = (x.max(0.0) * image_width).floor() as usize;
+        let y0 = (y.max(0.0) * image_height).floor() as usize;
+        let x1 = ((x + width).min(1.0) * image_width).ceil() as usize;
+        let y1 = ((y + height).min(1.0) * image_height).ceil() as usize;
+        Self::new(x0, y0, x1.saturating_sub(x0), y1.saturating_sub(y0))
+    }
+
+    // The part of the window that is inside an image of the given size
+    pub fn clip(&self, image_width: usize, image_height: usize) -> Self {
+        let x = self.x.min(image_width);
+        let y = self.y.min(image_height);
+        Self::new(
+            x,
+            y,
+            self.width.min(image_width - x),
+            self.height.min(image_height - y),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+
+    // Cut the window out of something laid out like an image, going across and then down
+    pub(crate) fn crop<T: Copy>(&self, data: &[T], image_width: usize) -> Box<[T]> {
+        (self.y..self.y + self.height)
+            .flat_map(|y| {
+                data[(y * image_width) + self.x..][..self.width]
+                    .iter()
+                    .copied()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crop_window() {
+        // Pixels that the window only partly covers are included
+        assert_eq!(
+            CropWindow::normalized((0.25, 0.1, 0.5, 0.5), 10, 10),
+            CropWindow::new(2, 1, 6, 5)
+        );
+        assert_eq!(
+            CropWindow::normalized((0.0, 0.0, 1.0, 1.0), 10, 6),
+            CropWindow::new(0, 0, 10, 6)
+        );
+
+        // Windows that hang over the edges of the image stop at them, and ones that are outside
+        // it altogether are empty
+        assert_eq!(
+            CropWindow::normalized((-0.5, 0.5, 1.0, 1.0), 10, 10),
+            CropWindow::new(0, 5, 5, 5)
+        );
+        assert!(CropWindow::normalized((1.5, 0.0, 0.5, 1.0), 10, 10).is_empty());
+        assert!(CropWindow::normalized((0.5, 0.5, 0.0, 0.5), 10, 10).is_empty());
+
+        assert_eq!(
+            CropWindow::new(6, 2, 10, 3).clip(8, 4),
+            CropWindow::new(6, 2, 2, 2)
+        );
+        assert_eq!(
+            CropWindow::new(1, 1, 2, 2).clip(8, 4),
+            CropWindow::new(1, 1, 2, 2)
+        );
+        let outside = CropWindow::new(20, 1, 2, 2).clip(8, 4);
+        assert_eq!(outside, CropWindow::new(8, 1, 0, 2));
+        assert!(outside.is_empty());
+
+        let data = (0..12).collect::<Vec<_>>();
+        assert_eq!(&*CropWindow::new(1, 1, 2, 2).crop(&data, 4), &[5, 6, 9, 10]);
+        assert_eq!(&*CropWindow::new(3, 0, 1, 3).crop(&data, 4), &[3, 7, 11]);
+        assert!(outside.clip(4, 3).crop(&data, 4).is_empty());
+        assert!(CropWindow::new(0, 3, 4, 0).crop(&data, 4).is_empty());
+    }
+}
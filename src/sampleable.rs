@@ -0,0 +1,23 @@
+use crate::{math::*, Sampler};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceSample {
+    pub point: Point3,
+    pub surface_normal: Vector3,
+    // The probability density of picking this point, per unit of surface area
+    pub pdf: FloatType,
+}
+
+// Sampleable is implemented by anything that can pick points on its own surface. This is what
+// lets us aim rays directly at lights. Shapes that don't know how to do that can just take the
+// defaults and will never be sampled.
+pub trait Sampleable {
+    fn sample_surface(&self, _t: FloatType, _sampler: &mut dyn Sampler) -> Option<SurfaceSample> {
+        None
+    }
+
+    // The area density that sample_surface would have produced the given point with
+    fn surface_pdf(&self, _point: Point3, _surface_normal: Vector3, _t: FloatType) -> FloatType {
+        0.0
+    }
+}
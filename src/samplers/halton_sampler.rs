@@ -0,0 +1,60 @@
+use super::{dimension_hash, mix_seed, u32_to_unit_float, Sampler};
+use crate::math::*;
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// The Halton sequence uses the radical inverse in a different prime base for each dimension.
+// Every pixel walks the same sequence, shifted by a random offset so that neighbouring pixels
+// don't line up. Paths that run out of primes carry on with plain random numbers.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel_index: u64,
+    sample_index: u64,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64, pixel_index: u64, sample_index: u64) -> Self {
+        Self {
+            seed,
+            pixel_index,
+            sample_index,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn next_1d(&mut self) -> FloatType {
+        let hash = dimension_hash(self.seed, self.pixel_index, self.dimension as u64);
+        let dimension = self.dimension;
+        self.dimension += 1;
+
+        let value = match PRIMES.get(dimension) {
+            Some(base) => {
+                let offset = u32_to_unit_float(hash as u32);
+                (radical_inverse(*base, self.sample_index) + offset).fract()
+            }
+            None => u32_to_unit_float(mix_seed(hash ^ self.sample_index) as u32),
+        };
+
+        value.min(1.0 - FloatType::EPSILON)
+    }
+}
+
+fn radical_inverse(base: u64, mut index: u64) -> FloatType {
+    let inverse_base = 1.0 / (base as f64);
+    let mut digit_scale = inverse_base;
+    let mut value = 0.0;
+
+    while index > 0 {
+        value += ((index % base) as f64) * digit_scale;
+        index /= base;
+        digit_scale *= inverse_base;
+    }
+
+    value as FloatType
+}
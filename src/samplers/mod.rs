@@ -0,0 +1,13 @@
+mod halton_sampler;
+mod random_sampler;
+mod sampler;
+mod sobol_sampler;
+mod stratified_sampler;
+
+pub use halton_sampler::HaltonSampler;
+pub use random_sampler::RandomSampler;
+pub use sampler::{Sampler, SamplerKind};
+pub use sobol_sampler::SobolSampler;
+pub use stratified_sampler::StratifiedSampler;
+
+pub(crate) use sampler::{dimension_hash, mix_seed, u32_to_unit_float};
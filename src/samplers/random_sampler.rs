@@ -0,0 +1,22 @@
+use super::{mix_seed, Sampler};
+use crate::math::*;
+use random_fast_rng::{FastRng, Random};
+
+// Independent random numbers for every dimension, with no attempt to spread them out
+pub struct RandomSampler(FastRng);
+
+impl RandomSampler {
+    pub fn new(seed: u64, pixel_index: u64, sample_index: u64) -> Self {
+        Self(FastRng::seed(
+            mix_seed(seed ^ mix_seed(pixel_index)),
+            sample_index,
+        ))
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn next_1d(&mut self) -> FloatType {
+        // The generator can round up to exactly 1.0, which is outside the range we promise
+        self.0.gen::<FloatType>().min(1.0 - FloatType::EPSILON)
+    }
+}
@@ -0,0 +1,116 @@
+use super::{HaltonSampler, RandomSampler, SobolSampler, StratifiedSampler};
+use crate::{math::*, Named};
+
+// A Sampler hands out all of the random numbers used while tracing one camera sample. Every
+// sample gets its own sampler, seeded from where it is in the image, so the same seed always
+// gives the same picture whichever thread happens to trace it.
+//
+// The numbers are handed out one dimension at a time, and the low discrepancy samplers spread
+// each dimension out evenly across all of the samples in a pixel. That only works if every
+// sample uses the dimensions for the same thing, so everything here warps its numbers into
+// shape rather than rejecting ones it doesn't like.
+pub trait Sampler {
+    // A number in [0, 1)
+    fn next_1d(&mut self) -> FloatType;
+
+    fn next_2d(&mut self) -> Point2 {
+        let x = self.next_1d();
+        let y = self.next_1d();
+        point2(x, y)
+    }
+
+    fn in_range(&mut self, min: FloatType, max: FloatType) -> FloatType {
+        (self.next_1d() * (max - min)) + min
+    }
+
+    fn unit_vector(&mut self) -> Vector3 {
+        let u = self.next_2d();
+        let z = 1.0 - (2.0 * u.x);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let a = 2.0 * constants::PI * u.y;
+        vec3(r * a.cos(), r * a.sin(), z)
+    }
+
+    fn in_unit_sphere(&mut self) -> Vector3 {
+        // The volume inside radius r grows with r^3, so taking the cube root spreads the points
+        // out evenly through the ball
+        let direction = self.unit_vector();
+        direction * self.next_1d().cbrt()
+    }
+
+    fn in_unit_disk(&mut self) -> Vector3 {
+        // Shirley and Chiu's concentric mapping, which takes squares in the unit square to rings
+        // on the disk and so keeps well spread out points well spread out
+        let u = self.next_2d();
+        let (x, y) = ((2.0 * u.x) - 1.0, (2.0 * u.y) - 1.0);
+        if x == 0.0 && y == 0.0 {
+            return vec3(0.0, 0.0, 0.0);
+        }
+
+        let (r, theta) = if x.abs() > y.abs() {
+            (x, (constants::PI / 4.0) * (y / x))
+        } else {
+            (y, (constants::PI / 2.0) - ((constants::PI / 4.0) * (x / y)))
+        };
+        vec3(r * theta.cos(), r * theta.sin(), 0.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplerKind {
+    Random,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl Named for SamplerKind {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("random", SamplerKind::Random),
+        ("stratified", SamplerKind::Stratified),
+        ("halton", SamplerKind::Halton),
+        ("sobol", SamplerKind::Sobol),
+    ];
+}
+
+impl SamplerKind {
+    // Make the sampler for one sample of a pixel. The sample count is how many samples we expect
+    // the pixel to get, which is what the stratified sampler divides its strata up by.
+    pub fn sampler(
+        &self,
+        seed: u64,
+        pixel_index: u64,
+        sample_index: u64,
+        sample_count: u64,
+    ) -> Box<dyn Sampler> {
+        match self {
+            Self::Random => Box::new(RandomSampler::new(seed, pixel_index, sample_index)),
+            Self::Stratified => Box::new(StratifiedSampler::new(
+                seed,
+                pixel_index,
+                sample_index,
+                sample_count,
+            )),
+            Self::Halton => Box::new(HaltonSampler::new(seed, pixel_index, sample_index)),
+            Self::Sobol => Box::new(SobolSampler::new(seed, pixel_index, sample_index)),
+        }
+    }
+}
+
+// SplitMix64, which spreads nearby inputs (neighbouring pixels, say) out over the whole range
+pub fn mix_seed(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// A hash for a particular dimension of a particular pixel, for scrambling and shuffling with
+pub fn dimension_hash(seed: u64, pixel_index: u64, dimension: u64) -> u64 {
+    mix_seed(mix_seed(seed ^ mix_seed(pixel_index)) ^ dimension)
+}
+
+// Turn the top bits of a u32 into a float in [0, 1), without rounding up to 1
+pub fn u32_to_unit_float(value: u32) -> FloatType {
+    ((value >> 8) as FloatType) * (1.0 / ((1u32 << 24) as FloatType))
+}
@@ -0,0 +1,124 @@
+use super::{dimension_hash, mix_seed, u32_to_unit_float, Sampler};
+use crate::math::*;
+
+// Owen scrambled Sobol points, following Burley's "Practical Hash-based Owen Scrambling". We only
+// use the first two Sobol dimensions, which make a very well spread out 2D pattern, and pad out
+// to as many dimensions as a path needs by giving every pair its own shuffle of the sample order.
+// Single numbers take one half of a pair, and the other half is kept for the next one.
+pub struct SobolSampler {
+    seed: u64,
+    pixel_index: u64,
+    sample_index: u32,
+    dimension: u64,
+    spare: Option<u32>,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64, pixel_index: u64, sample_index: u64) -> Self {
+        Self {
+            seed,
+            pixel_index,
+            sample_index: sample_index as u32,
+            dimension: 0,
+            spare: None,
+        }
+    }
+
+    fn next_pair(&mut self) -> (u32, u32) {
+        let hash = dimension_hash(self.seed, self.pixel_index, self.dimension);
+        self.dimension += 1;
+
+        // The shuffle and the two scrambles each need their own seed, with no bits in common
+        let y_hash = mix_seed(hash);
+        let index = nested_uniform_scramble(self.sample_index, hash as u32);
+        let x = nested_uniform_scramble(sobol_0(index), (hash >> 32) as u32);
+        let y = nested_uniform_scramble(sobol_1(index), y_hash as u32);
+        (x, y)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn next_1d(&mut self) -> FloatType {
+        let value = match self.spare.take() {
+            Some(value) => value,
+            None => {
+                let (x, y) = self.next_pair();
+                self.spare = Some(y);
+                x
+            }
+        };
+        u32_to_unit_float(value)
+    }
+
+    fn next_2d(&mut self) -> Point2 {
+        let (x, y) = self.next_pair();
+        point2(u32_to_unit_float(x), u32_to_unit_float(y))
+    }
+}
+
+// The first Sobol dimension is the van der Corput sequence
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+// The second dimension's direction numbers each come from the one before by v ^ (v >> 1)
+fn sobol_1(mut index: u32) -> u32 {
+    let mut direction = 1u32 << 31;
+    let mut value = 0;
+
+    while index != 0 {
+        if index & 1 != 0 {
+            value ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+
+    value
+}
+
+fn laine_karras_permutation(mut value: u32, seed: u32) -> u32 {
+    value ^= value.wrapping_mul(0x3d20_adea);
+    value = value.wrapping_add(seed);
+    value = value.wrapping_mul((seed >> 16) | 1);
+    value ^= value.wrapping_mul(0x0552_6c56);
+    value ^= value.wrapping_mul(0x53a2_2864);
+    value
+}
+
+fn nested_uniform_scramble(value: u32, seed: u32) -> u32 {
+    laine_karras_permutation(value.reverse_bits(), seed).reverse_bits()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sobol_points_are_stratified() {
+        // Any power of two number of Owen scrambled Sobol points puts exactly one point in each
+        // of the elementary intervals of that size, so with 16 points every 4x4 cell gets one
+        for dimension in 0..4 {
+            let mut sampler_cells = [0; 16];
+
+            for sample_index in 0..16 {
+                let mut sampler = SobolSampler::new(7, 3, sample_index);
+                sampler.dimension = dimension;
+                let point = sampler.next_2d();
+                let cell = ((point.y * 4.0) as usize * 4) + (point.x * 4.0) as usize;
+                sampler_cells[cell] += 1;
+            }
+
+            assert!(sampler_cells.iter().all(|count| *count == 1));
+        }
+
+        // Two single numbers in a row are the two halves of the same pair, and a pair in
+        // between doesn't get in the way of that
+        let mut pairs = SobolSampler::new(7, 3, 5);
+        let mut singles = SobolSampler::new(7, 3, 5);
+        let (first, second) = (pairs.next_2d(), pairs.next_2d());
+        assert_eq!(singles.next_1d(), first.x);
+        assert_eq!(singles.next_2d(), second);
+        assert_eq!(singles.next_1d(), first.y);
+    }
+}
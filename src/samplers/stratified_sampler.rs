@@ -0,0 +1,76 @@
+use super::{dimension_hash, mix_seed, u32_to_unit_float, Sampler};
+use crate::math::*;
+
+// Splits every dimension up into as many strata as there are samples in the pixel, and gives
+// each sample its own stratum, in a different shuffled order for each dimension. Once a pixel
+// has had all of its samples we start again with a fresh shuffle.
+pub struct StratifiedSampler {
+    seed: u64,
+    pixel_index: u64,
+    sample_index: u64,
+    sample_count: u64,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, pixel_index: u64, sample_index: u64, sample_count: u64) -> Self {
+        Self {
+            seed,
+            pixel_index,
+            sample_index,
+            sample_count: sample_count.max(1).min(u32::MAX as u64),
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn next_1d(&mut self) -> FloatType {
+        let round = self.sample_index / self.sample_count;
+        let index = (self.sample_index % self.sample_count) as u32;
+        let hash = dimension_hash(self.seed, self.pixel_index, self.dimension ^ (round << 32));
+        self.dimension += 1;
+
+        let stratum = permute(index, self.sample_count as u32, hash as u32);
+        let jitter = u32_to_unit_float(mix_seed(hash ^ self.sample_index) as u32);
+
+        (((stratum as FloatType) + jitter) / (self.sample_count as FloatType))
+            .min(1.0 - FloatType::EPSILON)
+    }
+}
+
+// Kensler's hashed permutation from "Correlated Multi-Jittered Sampling", which shuffles
+// 0..count without having to build a table
+fn permute(mut index: u32, count: u32, seed: u32) -> u32 {
+    let mut mask = count - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170_893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929_eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | (seed >> 27));
+        index = index.wrapping_mul(0x6935_fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dc_b303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e50_1cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860_a3df);
+        index &= mask;
+        index ^= index >> 5;
+
+        if index < count {
+            return index.wrapping_add(seed) % count;
+        }
+    }
+}
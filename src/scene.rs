@@ -1,6 +1,8 @@
+use crate::samplers::mix_seed;
 use crate::Ray;
 use crate::{
-    math::*, sky::Sky, BoundingBox, Intersectable, KDTree, SkinnedHitResult, TimeDependentBounded,
+    math::*, sky::Sky, BoundingBox, IntersectResult, Intersectable, KDTree, RandomSampler,
+    Sampleable, Sampler, SkinnedHitResult, TimeDependentBounded,
 };
 use crate::{Camera, PreparedCamera};
 use crate::{CompoundVisible, DynVisible, Visible};
@@ -19,20 +21,73 @@ impl Scene {
             shapes: shapes.decompose(),
         }
     }
+
+    // A hash of what the scene looks like, for telling whether two renders were of the same
+    // scene. It covers the camera, the sky, the bounding box of every shape between t0 and t1,
+    // and what each shape is made of as far as its material's Debug output goes. Only the bounds
+    // of the geometry are compared, so swapping a shape for another that fills the same box, like
+    // a sphere for a cube, isn't noticed. Image textures are only known by their size and noise
+    // textures by their scale. Each shape is hashed on its own and the hashes are sorted, so the
+    // order the shapes were added in doesn't matter.
+    pub fn fingerprint(&self, t0: FloatType, t1: FloatType) -> u64 {
+        let mut shape_hashes: Vec<u64> = self
+            .shapes
+            .iter()
+            .map(|shape| {
+                let bounding_box = shape.time_dependent_bounding_box(t0, t1);
+                let (min, max) = (bounding_box.min_point(), bounding_box.max_point());
+                let hash = [min.x, min.y, min.z, max.x, max.y, max.z]
+                    .iter()
+                    .fold(u64::from(shape.is_emissive()), |hash, value| {
+                        mix_seed(hash ^ u64::from(value.to_bits()))
+                    });
+                shape
+                    .material_description()
+                    .bytes()
+                    .fold(hash, |hash, byte| mix_seed(hash ^ u64::from(byte)))
+            })
+            .collect();
+        shape_hashes.sort_unstable();
+
+        let description = format!("{:?} {:?}", self.camera, self.sky);
+        let hash = description
+            .bytes()
+            .fold(0, |hash, byte| mix_seed(hash ^ u64::from(byte)));
+        shape_hashes
+            .into_iter()
+            .fold(hash, |hash, shape_hash| mix_seed(hash ^ shape_hash))
+    }
 }
 
 pub struct PreparedScene {
     camera: PreparedCamera,
     sky: Sky,
     root_volume: KDTree<DynVisible>,
+    emitters: Vec<usize>,
 }
 
 impl PreparedScene {
     pub fn make(scene: Scene, t0: FloatType, t1: FloatType) -> Self {
+        let root_volume = KDTree::snapshot(scene.shapes, t0, t1);
+
+        // Only keep hold of the emitters that we can actually pick points on. Anything else can
+        // still be found by scattered rays.
+        let mut sampler = RandomSampler::new(0, 0, 0);
+        let emitters = root_volume
+            .items()
+            .iter()
+            .enumerate()
+            .filter(|(_, visible)| {
+                visible.is_emissive() && visible.sample_surface(t0, &mut sampler).is_some()
+            })
+            .map(|(idx, _)| idx)
+            .collect();
+
         Self {
             camera: PreparedCamera::make(scene.camera, t0, t1),
             sky: scene.sky,
-            root_volume: KDTree::snapshot(scene.shapes, t0, t1),
+            root_volume,
+            emitters,
         }
     }
 
@@ -43,13 +98,103 @@ impl PreparedScene {
     pub fn sky(&self) -> &Sky {
         &self.sky
     }
+
+    pub fn emitters(&self) -> impl Iterator<Item = &DynVisible> {
+        let items = self.root_volume.items();
+        self.emitters.iter().map(move |idx| &items[*idx])
+    }
+
+    // Pick a direction from origin towards a point on one of the emitters. The emitter is chosen
+    // uniformly, so the density of the direction is given by emitter_pdf
+    pub fn sample_emitter(
+        &self,
+        origin: Point3,
+        t: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Vector3> {
+        if self.emitters.is_empty() {
+            return None;
+        }
+
+        let emitter_count = self.emitters.len();
+        let idx =
+            (sampler.in_range(0.0, emitter_count as FloatType) as usize).min(emitter_count - 1);
+        let emitter = &self.root_volume.items()[self.emitters[idx]];
+
+        emitter
+            .sample_surface(t, sampler)
+            .map(|sample| sample.point - origin)
+            .filter(|direction| direction.magnitude2() > 0.0)
+            .map(|direction| direction.normalize())
+    }
+
+    // The probability density, per unit solid angle, that sample_emitter would pick the
+    // direction of the given ray
+    pub fn emitter_pdf(&self, ray: &Ray, sampler: &mut dyn Sampler) -> FloatType {
+        if self.emitters.is_empty() {
+            return 0.0;
+        }
+
+        let ray = Ray::new(ray.origin(), ray.direction().normalize(), ray.time());
+        let pdf_sum: FloatType = self
+            .emitters()
+            .map(|emitter| emitter_direction_pdf(emitter, &ray, sampler))
+            .sum();
+
+        pdf_sum / (self.emitters.len() as FloatType)
+    }
+}
+
+fn emitter_direction_pdf(emitter: &DynVisible, ray: &Ray, sampler: &mut dyn Sampler) -> FloatType {
+    // A ray can pass through an emitter more than once (think of a sphere) and any of those points
+    // could have been the one we picked, so we need to add them all up
+    let mut pdf = 0.0;
+    let mut t_min = 0.001;
+
+    while let Some(hit_result) = emitter.intersect(ray, t_min, constants::INFINITY, sampler) {
+        let to_hit = hit_result.hit_point() - ray.origin();
+        let cosine = hit_result.surface_normal().dot(ray.direction()).abs();
+
+        if cosine > constants::EPSILON {
+            let area_pdf = emitter.surface_pdf(
+                hit_result.hit_point(),
+                hit_result.surface_normal(),
+                ray.time(),
+            );
+            pdf += area_pdf * to_hit.magnitude2() / cosine;
+        }
+
+        t_min = hit_result.distance() + 0.001;
+    }
+
+    pdf
+}
+
+impl PreparedScene {
+    // Intersect, and also find out which of the shapes in the scene was hit. The index doesn't
+    // mean anything on its own, but it is the same for every ray that hits the same shape.
+    pub fn intersect_shape(
+        &self,
+        ray: &Ray,
+        t_min: FloatType,
+        t_max: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(usize, SkinnedHitResult)> {
+        self.root_volume.intersect_item(ray, t_min, t_max, sampler)
+    }
 }
 
 impl Intersectable for PreparedScene {
     type Result = SkinnedHitResult;
 
-    fn intersect(&self, ray: &Ray, t_min: FloatType, t_max: FloatType) -> Option<SkinnedHitResult> {
-        self.root_volume.intersect(ray, t_min, t_max)
+    fn intersect(
+        &self,
+        ray: &Ray,
+        t_min: FloatType,
+        t_max: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<SkinnedHitResult> {
+        self.root_volume.intersect(ray, t_min, t_max, sampler)
     }
 }
 
@@ -58,3 +203,35 @@ impl TimeDependentBounded for PreparedScene {
         self.root_volume.time_dependent_bounding_box(t0, t1)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use crate::Skinnable;
+
+    #[test]
+    fn test_fingerprint_covers_materials() {
+        let scene = |color| {
+            let mut shapes = CompoundVisible::default();
+            shapes.push(
+                sphere(Point3::new(0.0, 0.0, -3.0), 1.0)
+                    .apply_material(lambertian(solid_texture(color))),
+            );
+            let camera = Camera::new(
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(0.0, 0.0, -1.0),
+                vec3(0.0, 1.0, 0.0),
+                Deg(60.0).into(),
+                1.0,
+                0.0,
+                1.0,
+            );
+            Scene::new(camera, regular_sky(), shapes)
+        };
+
+        let white = scene(crate::constants::WHITE).fingerprint(0.0, 1.0);
+        assert_eq!(white, scene(crate::constants::WHITE).fingerprint(0.0, 1.0));
+        assert_ne!(white, scene(crate::constants::RED).fingerprint(0.0, 1.0));
+    }
+}
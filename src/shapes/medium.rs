@@ -1,12 +1,17 @@
 use crate::{
-    math::*, utils::*, BoundingBox, DefaultVisible, GeometryHitResult, IntersectResult,
-    Intersectable, Material, PartialScatterResult, Primitive, Ray, ScatterResult, SkinnedHitResult,
-    Texture, TimeDependentBounded,
+    math::*, BoundingBox, Color, DefaultVisible, GeometryHitResult, IntersectResult, Intersectable,
+    Material, PartialScatterResult, Primitive, Ray, Sampleable, Sampler, ScatterResult,
+    SkinnedHitResult, Texture, TimeDependentBounded,
 };
 use std::sync::Arc;
 
 pub trait MediumDensity: Send + Sync + std::fmt::Debug {
-    fn does_scatter(&self, ray: Ray, ray_length: FloatType) -> Option<FloatType>;
+    fn does_scatter(
+        &self,
+        ray: Ray,
+        ray_length: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<FloatType>;
 }
 
 #[derive(Debug)]
@@ -25,13 +30,17 @@ impl<Density: MediumDensity, Phase: Material, Child: Primitive> Medium<Density,
         }
     }
 
-    fn double_intersect(&self, ray: &Ray) -> Option<(GeometryHitResult, GeometryHitResult)> {
-        if let Some(hit_1) = self
-            .child
-            .intersect(ray, -constants::INFINITY, constants::INFINITY)
+    fn double_intersect(
+        &self,
+        ray: &Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(GeometryHitResult, GeometryHitResult)> {
+        if let Some(hit_1) =
+            self.child
+                .intersect(ray, -constants::INFINITY, constants::INFINITY, sampler)
         {
             self.child
-                .intersect(ray, hit_1.distance() + 0.0001, constants::INFINITY)
+                .intersect(ray, hit_1.distance() + 0.0001, constants::INFINITY, sampler)
                 .map(|hit_2| (hit_1, hit_2))
         } else {
             None
@@ -44,8 +53,14 @@ impl<Density: 'static + MediumDensity, Phase: 'static + Material, Child: Primiti
 {
     type Result = SkinnedHitResult;
 
-    fn intersect(&self, ray: &Ray, t_min: FloatType, t_max: FloatType) -> Option<SkinnedHitResult> {
-        if let Some((hit_result_1, hit_result_2)) = self.double_intersect(ray) {
+    fn intersect(
+        &self,
+        ray: &Ray,
+        t_min: FloatType,
+        t_max: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<SkinnedHitResult> {
+        if let Some((hit_result_1, hit_result_2)) = self.double_intersect(ray, sampler) {
             let distance_1 = hit_result_1.distance().max(t_min).max(0.0);
             let distance_2 = hit_result_2.distance().min(t_max);
 
@@ -55,12 +70,15 @@ impl<Density: 'static + MediumDensity, Phase: 'static + Material, Child: Primiti
                 let internal_ray_length = distance_2 - distance_1;
 
                 self.density
-                    .does_scatter(internal_ray, internal_ray_length)
-                    .map(|scatter_distance| {
+                    .does_scatter(internal_ray, internal_ray_length, sampler)
+                    .map(|scatter_distance| scatter_distance + distance_1)
+                    // Rounding can put a scatter right where the ray leaves, which isn't inside
+                    .filter(|distance| *distance < distance_2)
+                    .map(|distance| {
                         SkinnedHitResult::new(
                             GeometryHitResult::new(
                                 ray,
-                                scatter_distance + distance_1,
+                                distance,
                                 vec3(1.0, 0.0, 0.0), // arbitrary
                                 vec3(0.0, 1.0, 0.0), // arbitrary
                                 vec3(0.0, 0.0, 1.0), // arbitrary
@@ -87,9 +105,17 @@ impl<Density: 'static + MediumDensity, Phase: 'static + Material, Child: Primiti
     }
 }
 
+impl<Density: 'static + MediumDensity, Phase: 'static + Material, Child: Primitive> Sampleable
+    for Medium<Density, Phase, Child>
+{
+}
+
 impl<Density: 'static + MediumDensity, Phase: 'static + Material, Child: Primitive> DefaultVisible
     for Medium<Density, Phase, Child>
 {
+    fn material_description(&self) -> String {
+        format!("{:?} {:?}", self.density, self.phase)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -106,8 +132,13 @@ impl ConstantDensity {
 }
 
 impl MediumDensity for ConstantDensity {
-    fn does_scatter(&self, _ray: Ray, ray_length: FloatType) -> Option<FloatType> {
-        let hit_distance = self.negative_inverse_density * random_in_range(0.0, 1.0).ln();
+    fn does_scatter(
+        &self,
+        _ray: Ray,
+        ray_length: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<FloatType> {
+        let hit_distance = self.negative_inverse_density * (1.0 - sampler.next_1d()).ln();
         if hit_distance <= ray_length {
             Some(hit_distance)
         } else {
@@ -116,23 +147,54 @@ impl MediumDensity for ConstantDensity {
     }
 }
 
+// Isotropic scattering sends light equally in every direction
+const ISOTROPIC_PDF: FloatType = 1.0 / (4.0 * constants::PI);
+
 #[derive(Debug)]
 pub struct Isotropic<Albedo: Texture>(Albedo);
 
 impl<Albedo: Texture + Clone> Material for Isotropic<Albedo> {
-    fn scatter(&self, ray_in: &Ray, hit_record: GeometryHitResult) -> Option<ScatterResult> {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: GeometryHitResult,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
         let attenuation =
             cgmath::Vector4::from(self.0.value(hit_record.hit_point(), hit_record.uv())).truncate();
 
         Some(ScatterResult {
-            partial: PartialScatterResult { attenuation },
+            partial: PartialScatterResult {
+                attenuation,
+                pdf: Some(ISOTROPIC_PDF),
+            },
             scattered: Ray::new(
                 hit_record.hit_point(),
-                random_in_unit_sphere(),
+                sampler.in_unit_sphere(),
                 ray_in.time(),
             ),
         })
     }
+
+    fn scatter_towards(
+        &self,
+        _ray_in: &Ray,
+        hit_record: GeometryHitResult,
+        _direction: Vector3,
+    ) -> Option<PartialScatterResult> {
+        let attenuation =
+            cgmath::Vector4::from(self.0.value(hit_record.hit_point(), hit_record.uv())).truncate()
+                * ISOTROPIC_PDF;
+
+        Some(PartialScatterResult {
+            attenuation,
+            pdf: Some(ISOTROPIC_PDF),
+        })
+    }
+
+    fn albedo(&self, hit_record: &GeometryHitResult) -> Color {
+        self.0.value(hit_record.hit_point(), hit_record.uv())
+    }
 }
 
 pub mod factories {
@@ -172,9 +234,10 @@ fn test_constant_medium_hit_points() {
 
     let ray = Ray::new(Point3::new(0.0, 0.0, -10.0), vec3(0.0, 0.0, 1.0), 0.0);
 
+    let mut sampler = crate::RandomSampler::new(0, 0, 0);
     let hit_count = (0..1000000)
         .into_iter()
-        .filter_map(|_| medium.intersect(&ray, 0.0001, constants::INFINITY))
+        .filter_map(|_| medium.intersect(&ray, 0.0001, constants::INFINITY, &mut sampler))
         .map(|intersect| {
             let hit_point = intersect.hit_point();
             let distance = intersect.distance();
@@ -187,7 +250,7 @@ fn test_constant_medium_hit_points() {
                 "hit_point.z {:?} not in range",
                 hit_point.z,
             );
-            assert!(distance >= 9.0 && distance < 11.0);
+            assert!((9.0..11.0).contains(&distance));
             assert_eq!(hit_point.z, distance - 10.0);
         })
         .count();
@@ -1,7 +1,7 @@
 use super::TriangleVertex;
 use crate::{
     math::*, Bounded, BoundingBox, DefaultPrimitive, DefaultSkinnable, DefaultTransformable,
-    GeometryHitResult, Intersectable, KDTree, Ray,
+    GeometryHitResult, Intersectable, KDTree, Ray, Sampleable, Sampler,
 };
 use anyhow::{anyhow, Result};
 use std::iter::FromIterator;
@@ -251,6 +251,7 @@ impl Intersectable for TriangleMesh {
         ray: &Ray,
         t_min: FloatType,
         t_max: FloatType,
+        _sampler: &mut dyn Sampler,
     ) -> Option<GeometryHitResult> {
         self.intersect_triangles
             .intersecting_blocks(ray, t_min, t_max)
@@ -321,6 +322,7 @@ impl Bounded for TriangleMesh {
     }
 }
 
+impl Sampleable for TriangleMesh {}
 impl DefaultTransformable for TriangleMesh {}
 impl DefaultSkinnable for TriangleMesh {}
 impl DefaultPrimitive for TriangleMesh {}
@@ -328,7 +330,7 @@ impl DefaultPrimitive for TriangleMesh {}
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::IntersectResult;
+    use crate::{IntersectResult, RandomSampler};
 
     #[test]
     fn test_triangle_mesh() {
@@ -406,6 +408,7 @@ mod test {
                 &Ray::new(Point3::new(0.5, 0.5, -10.0), vec3(0.0, 0.0, 1.0), 0.0),
                 0.0,
                 constants::INFINITY,
+                &mut RandomSampler::new(0, 0, 0),
             )
             .expect("Missing intersection");
 
@@ -418,6 +421,7 @@ mod test {
             &Ray::new(Point3::new(1.5, 1.5, -10.0), vec3(0.0, 0.0, 1.0), 0.0),
             0.0,
             constants::INFINITY,
+            &mut RandomSampler::new(0, 0, 0),
         );
         assert!(
             intersection.is_none(),
@@ -441,6 +445,7 @@ mod test {
                 &Ray::new(Point3::new(0.5, 0.5, -10.0), vec3(0.0, 0.0, 1.0), 0.0),
                 0.0,
                 constants::INFINITY,
+                &mut RandomSampler::new(0, 0, 0),
             )
             .expect("Missing intersection");
 
@@ -453,6 +458,7 @@ mod test {
             &Ray::new(Point3::new(1.5, 0.5, -10.0), vec3(0.0, 0.0, 1.0), 0.0),
             0.0,
             constants::INFINITY,
+            &mut RandomSampler::new(0, 0, 0),
         );
         assert!(
             intersection.is_none(),
@@ -1,6 +1,6 @@
 use crate::{
     math::*, Bounded, BoundingBox, DefaultPrimitive, DefaultSkinnable, DefaultTransformable,
-    GeometryHitResult, Intersectable, Ray,
+    GeometryHitResult, Intersectable, Ray, Sampleable, Sampler,
 };
 
 #[derive(Debug, Clone)]
@@ -18,6 +18,7 @@ impl Intersectable for ParabolaXY {
         ray: &Ray,
         t_min: FloatType,
         t_max: FloatType,
+        _sampler: &mut dyn Sampler,
     ) -> Option<GeometryHitResult> {
         // A paraboloid is all the points that are equidistant between the focus of the parabola, and the directrix plane,
         // which is a plane that does not pass through the focus.
@@ -168,6 +169,7 @@ impl Bounded for ParabolaXY {
     }
 }
 
+impl Sampleable for ParabolaXY {}
 impl DefaultTransformable for ParabolaXY {}
 impl DefaultSkinnable for ParabolaXY {}
 impl DefaultPrimitive for ParabolaXY {}
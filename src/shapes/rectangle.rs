@@ -1,6 +1,6 @@
 use crate::{
     math::*, Bounded, BoundingBox, DefaultPrimitive, DefaultSkinnable, DefaultTransformable,
-    GeometryHitResult, Intersectable, Ray, Transformable,
+    GeometryHitResult, Intersectable, Ray, Sampleable, Sampler, SurfaceSample, Transformable,
 };
 
 #[derive(Debug, Clone)]
@@ -26,6 +26,7 @@ impl Intersectable for UnitXyRectangle {
         ray: &Ray,
         t_min: FloatType,
         t_max: FloatType,
+        _sampler: &mut dyn Sampler,
     ) -> Option<GeometryHitResult> {
         let ray_origin = ray.origin();
         let ray_direction = ray.direction();
@@ -77,6 +78,25 @@ impl Bounded for UnitXyRectangle {
     }
 }
 
+impl Sampleable for UnitXyRectangle {
+    fn sample_surface(&self, _t: FloatType, sampler: &mut dyn Sampler) -> Option<SurfaceSample> {
+        Some(SurfaceSample {
+            point: Point3::new(
+                sampler.in_range(-0.5, 0.5),
+                sampler.in_range(-0.5, 0.5),
+                0.0,
+            ),
+            surface_normal: vec3(0.0, 0.0, 1.0),
+            pdf: 1.0,
+        })
+    }
+
+    fn surface_pdf(&self, _point: Point3, _surface_normal: Vector3, _t: FloatType) -> FloatType {
+        // The unit rectangle has an area of one
+        1.0
+    }
+}
+
 impl DefaultTransformable for UnitXyRectangle {}
 impl DefaultSkinnable for UnitXyRectangle {}
 impl DefaultPrimitive for UnitXyRectangle {}
@@ -1,6 +1,7 @@
 use crate::{
     math::*, Bounded, BoundingBox, DefaultPrimitive, DefaultSkinnable, DefaultTransformable,
-    GeometryHitResult, Intersectable, Ray, TimeDependentBounded,
+    GeometryHitResult, Intersectable, Ray, Sampleable, Sampler, SurfaceSample,
+    TimeDependentBounded,
 };
 
 fn get_sphere_uv(p: Vector3) -> Point2 {
@@ -13,6 +14,24 @@ fn get_sphere_uv(p: Vector3) -> Point2 {
     point2(u, v)
 }
 
+fn sample_sphere_surface(
+    center: Point3,
+    radius: FloatType,
+    sampler: &mut dyn Sampler,
+) -> SurfaceSample {
+    let surface_normal = sampler.unit_vector();
+
+    SurfaceSample {
+        point: center + (radius * surface_normal),
+        surface_normal,
+        pdf: sphere_surface_pdf(radius),
+    }
+}
+
+fn sphere_surface_pdf(radius: FloatType) -> FloatType {
+    1.0 / (4.0 * constants::PI * radius * radius)
+}
+
 #[derive(Clone, Debug)]
 pub struct Sphere {
     center: Point3,
@@ -136,6 +155,7 @@ impl Intersectable for Sphere {
         ray: &Ray,
         t_min: FloatType,
         t_max: FloatType,
+        _sampler: &mut dyn Sampler,
     ) -> Option<GeometryHitResult> {
         let ray_origin = ray.origin();
         let oc = ray_origin - self.center;
@@ -216,6 +236,16 @@ impl Bounded for Sphere {
     }
 }
 
+impl Sampleable for Sphere {
+    fn sample_surface(&self, _t: FloatType, sampler: &mut dyn Sampler) -> Option<SurfaceSample> {
+        Some(sample_sphere_surface(self.center, self.radius, sampler))
+    }
+
+    fn surface_pdf(&self, _point: Point3, _surface_normal: Vector3, _t: FloatType) -> FloatType {
+        sphere_surface_pdf(self.radius)
+    }
+}
+
 impl DefaultTransformable for Sphere {}
 impl DefaultSkinnable for Sphere {}
 impl DefaultPrimitive for Sphere {}
@@ -382,6 +412,7 @@ impl Intersectable for MovingSphere {
         ray: &Ray,
         t_min: FloatType,
         t_max: FloatType,
+        _sampler: &mut dyn Sampler,
     ) -> Option<GeometryHitResult> {
         let center = self.center(ray.time());
         let ray_origin = ray.origin();
@@ -465,6 +496,16 @@ impl TimeDependentBounded for MovingSphere {
     }
 }
 
+impl Sampleable for MovingSphere {
+    fn sample_surface(&self, t: FloatType, sampler: &mut dyn Sampler) -> Option<SurfaceSample> {
+        Some(sample_sphere_surface(self.center(t), self.radius, sampler))
+    }
+
+    fn surface_pdf(&self, _point: Point3, _surface_normal: Vector3, _t: FloatType) -> FloatType {
+        sphere_surface_pdf(self.radius)
+    }
+}
+
 impl DefaultPrimitive for MovingSphere {}
 impl DefaultTransformable for MovingSphere {}
 impl DefaultSkinnable for MovingSphere {}
@@ -508,7 +549,7 @@ pub mod factories {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{factories::*, IntersectResult};
+    use crate::{factories::*, IntersectResult, RandomSampler};
 
     #[test]
     fn test_sphere_normals() {
@@ -518,6 +559,7 @@ mod test {
             &Ray::new(Point3::new(0.0, 0.0, -10.0), vec3(0.0, 0.0, 1.0), 0.0),
             0.0,
             constants::INFINITY,
+            &mut RandomSampler::new(0, 0, 0),
         );
         assert!(result.is_some());
         let result = result.unwrap();
@@ -0,0 +1,123 @@
+use crate::{math::*, Named};
+
+// How open the shutter is over the exposure. A box is fully open the whole time. A trapezoid
+// opens and closes steadily over the ramp at either end, and a triangle is a trapezoid that does
+// nothing else, so that moving things fade out at the ends of their blur.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutterCurve {
+    Box,
+    Triangle,
+    Trapezoid,
+}
+
+impl Named for ShutterCurve {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("box", ShutterCurve::Box),
+        ("triangle", ShutterCurve::Triangle),
+        ("trapezoid", ShutterCurve::Trapezoid),
+    ];
+}
+
+// The ramp is how much of the exposure a trapezoid spends opening, and again closing. A rolling
+// shutter reads the image out from the top to the bottom, taking this much of the time the
+// shutter is open to do it, so each row is exposed for the rest of the time, starting a little
+// later than the row above.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shutter {
+    pub curve: ShutterCurve,
+    pub ramp: FloatType,
+    pub rolling: FloatType,
+}
+
+impl Default for Shutter {
+    fn default() -> Self {
+        Self {
+            curve: ShutterCurve::Box,
+            ramp: 0.25,
+            rolling: 0.0,
+        }
+    }
+}
+
+impl Shutter {
+    // The time for a ray, given a random number and how far up the image the ray is
+    pub(crate) fn time(
+        &self,
+        u: FloatType,
+        t0: FloatType,
+        t1: FloatType,
+        t: FloatType,
+    ) -> FloatType {
+        let rolling = self.rolling.clamp(0.0, 1.0);
+        let duration = t1 - t0;
+        let start = t0 + (rolling * duration * (1.0 - t.clamp(0.0, 1.0)));
+        start + (self.fraction(u) * ((1.0 - rolling) * duration))
+    }
+
+    // How far through the exposure a random number goes, such that times come up in proportion
+    // to how open the shutter is
+    fn fraction(&self, u: FloatType) -> FloatType {
+        let ramp = match self.curve {
+            ShutterCurve::Box => return u,
+            ShutterCurve::Triangle => 0.5,
+            ShutterCurve::Trapezoid => self.ramp.clamp(0.0, 0.5),
+        };
+        if ramp <= 0.0 {
+            return u;
+        }
+
+        // The curve is one high in the middle, so the area under it is one less the ramp, and
+        // the opening and closing ramps each have half the ramp under them
+        let area = u * (1.0 - ramp);
+        let ramp_area = ramp / 2.0;
+        if area < ramp_area {
+            (2.0 * area * ramp).sqrt()
+        } else if area <= (1.0 - ramp) - ramp_area {
+            ramp + (area - ramp_area)
+        } else {
+            1.0 - (2.0 * ((1.0 - ramp) - area) * ramp).max(0.0).sqrt()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shutter_curves() {
+        for (_, curve) in ShutterCurve::NAMES.iter() {
+            let shutter = Shutter {
+                curve: *curve,
+                ..Shutter::default()
+            };
+
+            // Every curve covers the whole exposure, in order
+            let fractions: Vec<_> = (0..=100)
+                .map(|i| shutter.fraction((i as FloatType) / 100.0))
+                .collect();
+            assert!(fractions[0].abs() < 0.0001, "{:?}", curve);
+            assert!((fractions[100] - 1.0).abs() < 0.0001, "{:?}", curve);
+            assert!(fractions.windows(2).all(|w| w[0] <= w[1]), "{:?}", curve);
+            assert!((fractions[50] - 0.5).abs() < 0.0001, "{:?}", curve);
+        }
+
+        // An eighth of a triangle's samples are in the first half of its opening ramp
+        let triangle = Shutter {
+            curve: ShutterCurve::Triangle,
+            ..Shutter::default()
+        };
+        assert!((triangle.fraction(0.125) - 0.25).abs() < 0.0001);
+
+        // With half of the time spent reading out, the top row is exposed for the first half and
+        // the bottom row for the second
+        let rolling = Shutter {
+            rolling: 0.5,
+            ..Shutter::default()
+        };
+        assert_eq!(rolling.time(0.0, 2.0, 4.0, 1.0), 2.0);
+        assert_eq!(rolling.time(1.0, 2.0, 4.0, 1.0), 3.0);
+        assert_eq!(rolling.time(0.0, 2.0, 4.0, 0.0), 3.0);
+        assert_eq!(rolling.time(1.0, 2.0, 4.0, 0.0), 4.0);
+    }
+}
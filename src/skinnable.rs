@@ -1,6 +1,7 @@
 use crate::{
     math::*, BaseMaterial, CompoundVisible, DynVisible, GeometryHitResult, IntersectResult,
-    Intersectable, Primitive, TimeDependentBounded, Transformable, Visible, WrappedIntersectResult,
+    Intersectable, Primitive, Sampleable, Sampler, SurfaceSample, TimeDependentBounded,
+    Transformable, Visible, WrappedIntersectResult,
 };
 use std::sync::Arc;
 
@@ -59,9 +60,10 @@ impl<P: Intersectable<Result = GeometryHitResult>> Intersectable for Skinned<P>
         ray: &crate::Ray,
         t_min: crate::math::FloatType,
         t_max: crate::math::FloatType,
+        sampler: &mut dyn Sampler,
     ) -> Option<Self::Result> {
         self.primitive
-            .intersect(ray, t_min, t_max)
+            .intersect(ray, t_min, t_max, sampler)
             .map(|hit_result| hit_result.apply_shared_material(self.material.clone()))
     }
 }
@@ -76,6 +78,16 @@ impl<P: TimeDependentBounded> TimeDependentBounded for Skinned<P> {
     }
 }
 
+impl<P: Sampleable> Sampleable for Skinned<P> {
+    fn sample_surface(&self, t: FloatType, sampler: &mut dyn Sampler) -> Option<SurfaceSample> {
+        self.primitive.sample_surface(t, sampler)
+    }
+
+    fn surface_pdf(&self, point: Point3, surface_normal: Vector3, t: FloatType) -> FloatType {
+        self.primitive.surface_pdf(point, surface_normal, t)
+    }
+}
+
 impl<P: Transformable> Transformable for Skinned<P> {
     type Target = Skinned<<P as Transformable>::Target>;
 
@@ -127,4 +139,12 @@ impl<P: 'static + Intersectable + TimeDependentBounded + Primitive> Visible for
             })
             .collect()
     }
+
+    fn is_emissive(&self) -> bool {
+        self.material.base_is_emissive()
+    }
+
+    fn material_description(&self) -> String {
+        format!("{:?}", self.material)
+    }
 }
@@ -1,17 +1,23 @@
 use crate::math::*;
-use crate::{Color, Texture};
+use crate::{Color, ColorSpace, Texture};
 use image::{GenericImageView, Pixel};
 
+// The color space says how to read the values in the image. Colors are normally sRGB, and are
+// turned back into linear light before the renderer sees them, but anything that isn't a color,
+// like a normal map, should be linear so that it comes through untouched.
 #[derive(Clone)]
-pub struct ImageTexture<Image: GenericImageView + Sync + Send>(Image);
+pub struct ImageTexture<Image: GenericImageView + Sync + Send> {
+    image: Image,
+    color_space: ColorSpace,
+}
 
 impl<Image: GenericImageView + Sync + Send> ImageTexture<Image> {
-    pub fn new(image: Image) -> Self {
-        Self(image)
+    pub fn new(image: Image, color_space: ColorSpace) -> Self {
+        Self { image, color_space }
     }
 
     fn image(&self) -> &Image {
-        &self.0
+        &self.image
     }
 }
 
@@ -23,13 +29,16 @@ impl<Image: GenericImageView + Sync + Send> Texture for ImageTexture<Image> {
         let i = ((u * (self.image().width() as FloatType)) as u32).min(self.image().width() - 1);
         let j = ((v * (self.image().height() as FloatType)) as u32).min(self.image().height() - 1);
 
-        self.image().get_pixel(i, j).to_rgb().into()
+        Color::from(self.image().get_pixel(i, j).to_rgb()).decode(self.color_space)
     }
 }
 
 impl<Image: GenericImageView + Sync + Send> std::fmt::Debug for ImageTexture<Image> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("ImageTexture").finish()
+        f.debug_struct("ImageTexture")
+            .field("dimensions", &self.image.dimensions())
+            .field("color_space", &self.color_space)
+            .finish()
     }
 }
 
@@ -38,7 +47,8 @@ pub mod factories {
 
     pub fn image_texture<Image: image::GenericImageView + Sync + Send>(
         image: Image,
+        color_space: ColorSpace,
     ) -> ImageTexture<Image> {
-        ImageTexture::new(image)
+        ImageTexture::new(image, color_space)
     }
 }
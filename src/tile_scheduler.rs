@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+pub const TILE_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    // Cut an image up into tiles, going across and then down. The tiles on the right and bottom
+    // edges are smaller if the image doesn't divide up evenly.
+    pub fn split(image_width: usize, image_height: usize, tile_size: usize) -> Vec<Tile> {
+        let tile_size = tile_size.max(1);
+
+        (0..image_height)
+            .step_by(tile_size)
+            .flat_map(|y| {
+                (0..image_width).step_by(tile_size).map(move |x| Tile {
+                    x,
+                    y,
+                    width: tile_size.min(image_width - x),
+                    height: tile_size.min(image_height - y),
+                })
+            })
+            .collect()
+    }
+
+    // The part of this tile that is also in the other one, if there is any
+    pub fn intersect(&self, other: &Tile) -> Option<Tile> {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        if right > x && bottom > y {
+            Some(Tile {
+                x,
+                y,
+                width: right - x,
+                height: bottom - y,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn pixel_count(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> {
+        let Tile {
+            x,
+            y,
+            width,
+            height,
+        } = *self;
+        (y..y + height).flat_map(move |y| (x..x + width).map(move |x| (x, y)))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TileWork {
+    pub tile: Tile,
+    pub first_pass: usize,
+    pub passes: usize,
+}
+
+// Every worker has its own queue of work, which starts off as a contiguous run of tiles so
+// that each worker stays in roughly the same part of the scene. Once a worker has run out it
+// steals from the back of the other queues, which is the work their owners would get to last.
+pub struct TileScheduler {
+    queues: Box<[Mutex<VecDeque<TileWork>>]>,
+}
+
+impl TileScheduler {
+    pub fn new(worker_count: usize, work: Vec<TileWork>) -> Self {
+        let worker_count = worker_count.max(1);
+        let work_count = work.len();
+
+        let mut queues: Vec<VecDeque<TileWork>> =
+            (0..worker_count).map(|_| VecDeque::new()).collect();
+        for (idx, item) in work.into_iter().enumerate() {
+            queues[(idx * worker_count) / work_count].push_back(item);
+        }
+
+        Self {
+            queues: queues.into_iter().map(Mutex::new).collect(),
+        }
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.queues.len()
+    }
+
+    pub fn next(&self, worker: usize) -> Option<TileWork> {
+        if let Some(work) = self.queues[worker].lock().unwrap().pop_front() {
+            return Some(work);
+        }
+
+        (1..self.worker_count())
+            .map(|offset| (worker + offset) % self.worker_count())
+            .find_map(|victim| self.queues[victim].lock().unwrap().pop_back())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scheduler_hands_out_all_work() {
+        let tiles = Tile::split(100, 70, TILE_SIZE);
+        assert_eq!(tiles.iter().map(Tile::pixel_count).sum::<usize>(), 100 * 70);
+
+        let work = tiles
+            .iter()
+            .map(|tile| TileWork {
+                tile: *tile,
+                first_pass: 0,
+                passes: 1,
+            })
+            .collect();
+        let scheduler = TileScheduler::new(3, work);
+
+        // A single worker should be able to drain everything, stealing from the others
+        let mut handed_out = std::iter::from_fn(|| scheduler.next(0))
+            .map(|work| work.tile)
+            .collect::<Vec<_>>();
+        handed_out.sort_by_key(|tile| (tile.y, tile.x));
+        assert_eq!(handed_out, tiles);
+    }
+
+    #[test]
+    fn test_tile_intersect() {
+        let tile = |x, y, width, height| Tile {
+            x,
+            y,
+            width,
+            height,
+        };
+        let a = tile(0, 0, 32, 32);
+
+        assert_eq!(a.intersect(&tile(16, 8, 32, 32)), Some(tile(16, 8, 16, 24)));
+        assert_eq!(a.intersect(&tile(4, 4, 8, 8)), Some(tile(4, 4, 8, 8)));
+        assert_eq!(tile(4, 4, 8, 8).intersect(&a), Some(tile(4, 4, 8, 8)));
+
+        // Tiles that only touch along an edge, or are empty, have nothing in common
+        assert_eq!(a.intersect(&tile(32, 0, 32, 32)), None);
+        assert_eq!(a.intersect(&tile(0, 32, 32, 32)), None);
+        assert_eq!(a.intersect(&tile(8, 8, 0, 8)), None);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::color::luminance;
+use crate::{math::*, Color, Named, VectorImage};
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapOperator {
+    Clamp,
+    Reinhard,
+    ExtendedReinhard,
+    Aces,
+    Hable,
+}
+
+impl Named for ToneMapOperator {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("clamp", ToneMapOperator::Clamp),
+        ("reinhard", ToneMapOperator::Reinhard),
+        ("reinhard-extended", ToneMapOperator::ExtendedReinhard),
+        ("aces", ToneMapOperator::Aces),
+        ("hable", ToneMapOperator::Hable),
+    ];
+}
+
+// Hable's curve is normally used with a white point of 11.2, and a bias that brings the
+// exposure up to where the curve looks best
+const HABLE_WHITE_POINT: FloatType = 11.2;
+const HABLE_EXPOSURE_BIAS: FloatType = 2.0;
+
+// Turns the linear light from a render into values between 0 and 1 for an image that can't go
+// any brighter than that. The exposure is in stops, so every step up doubles the brightness
+// before the operator squeezes it down.
+#[derive(Debug, Clone, Copy)]
+pub struct ToneMapper {
+    pub exposure: FloatType,
+    pub operator: ToneMapOperator,
+
+    // The brightness that comes out as white, for the operators that have one. With none, the
+    // extended Reinhard operator uses the brightest pixel in the image and Hable uses 11.2.
+    pub white_point: Option<FloatType>,
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            operator: ToneMapOperator::Clamp,
+            white_point: None,
+        }
+    }
+}
+
+impl ToneMapper {
+    pub fn apply(&self, image: &VectorImage) -> Vec<Color> {
+        let scale = self.exposure.exp2();
+        let pixels: Vec<_> = image.averaged_pixels().map(|pixel| pixel * scale).collect();
+
+        let white_point = match (self.white_point, self.operator) {
+            (Some(white_point), _) => white_point,
+            (None, ToneMapOperator::Hable) => HABLE_WHITE_POINT,
+            (None, _) => pixels
+                .iter()
+                .map(|pixel| luminance(*pixel))
+                .fold(0.0, FloatType::max),
+        };
+
+        pixels
+            .into_iter()
+            .map(|pixel| Color::try_from(self.map_pixel(pixel, white_point)).unwrap())
+            .collect()
+    }
+
+    fn map_pixel(&self, pixel: Vector3, white_point: FloatType) -> Vector3 {
+        match self.operator {
+            ToneMapOperator::Clamp => pixel,
+
+            // Reinhard works on the brightness, and scales the color to match, so that it
+            // doesn't change the hue of bright colors
+            ToneMapOperator::Reinhard => scale_luminance(pixel, |l| l / (1.0 + l)),
+            ToneMapOperator::ExtendedReinhard => {
+                let white_squared = (white_point * white_point).max(constants::EPSILON);
+                scale_luminance(pixel, |l| l * (1.0 + (l / white_squared)) / (1.0 + l))
+            }
+
+            // Narkowicz's fit to the ACES filmic curve, which expects the exposure to be brought
+            // down a bit first
+            ToneMapOperator::Aces => pixel.map(|x| {
+                let x = x * 0.6;
+                ((x * ((2.51 * x) + 0.03)) / ((x * ((2.43 * x) + 0.59)) + 0.14)).max(0.0)
+            }),
+
+            ToneMapOperator::Hable => {
+                let white_scale = 1.0 / hable_curve(white_point.max(constants::EPSILON));
+                pixel.map(|x| hable_curve(x * HABLE_EXPOSURE_BIAS) * white_scale)
+            }
+        }
+    }
+}
+
+fn scale_luminance(pixel: Vector3, f: impl Fn(FloatType) -> FloatType) -> Vector3 {
+    let l = luminance(pixel);
+    if l > 0.0 {
+        pixel * (f(l) / l)
+    } else {
+        Vector3::zero()
+    }
+}
+
+// The filmic curve from Uncharted 2
+fn hable_curve(x: FloatType) -> FloatType {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * ((a * x) + (c * b)) + (d * e)) / (x * ((a * x) + b) + (d * f))) - (e / f)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tone_map_operators() {
+        let mut image = VectorImage::new(3, 1);
+        let values = [0.25, 1.0, 4.0];
+        for (pixel, value) in image.pixels_mut().zip(values.iter()) {
+            // Two samples in each pixel, so these get averaged first
+            *pixel = cgmath::vec4(*value, *value, *value, 1.0) * 2.0;
+        }
+
+        for (_, operator) in ToneMapOperator::NAMES.iter() {
+            let tone_mapper = ToneMapper {
+                operator: *operator,
+                ..ToneMapper::default()
+            };
+            let mapped: Vec<_> = tone_mapper
+                .apply(&image)
+                .into_iter()
+                .map(|color| color.get_r())
+                .collect();
+
+            // Every operator keeps things in order, and only clamping leaves values alone
+            assert!(mapped[0] < mapped[1] && mapped[1] < mapped[2]);
+            assert!(mapped[0] > 0.0, "{:?}", operator);
+            if *operator == ToneMapOperator::Clamp {
+                assert_eq!(mapped, values);
+            } else {
+                assert!(mapped[2] <= 1.0 + constants::EPSILON, "{:?}", operator);
+            }
+        }
+
+        // The brightest pixel comes out white, and a stop of exposure doubles everything
+        let tone_mapper = ToneMapper {
+            operator: ToneMapOperator::ExtendedReinhard,
+            ..ToneMapper::default()
+        };
+        assert!((tone_mapper.apply(&image)[2].get_r() - 1.0).abs() < 0.0001);
+
+        let tone_mapper = ToneMapper {
+            exposure: 1.0,
+            ..ToneMapper::default()
+        };
+        assert_eq!(tone_mapper.apply(&image)[0].get_r(), 0.5);
+    }
+}
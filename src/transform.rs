@@ -2,8 +2,8 @@ use std::sync::Arc;
 
 use crate::{
     math::*, BaseMaterial, BoundingBox, CompoundPrimitive, CompoundVisible, DynPrimitive,
-    DynVisible, GeometryHitResult, IntersectResult, Intersectable, Primitive, Ray, Skinnable,
-    SkinnedHitResult, TimeDependentBounded, Visible,
+    DynVisible, GeometryHitResult, IntersectResult, Intersectable, Primitive, Ray, Sampleable,
+    Sampler, Skinnable, SkinnedHitResult, SurfaceSample, TimeDependentBounded, Visible,
 };
 
 trait GeometryTransform {
@@ -22,6 +22,16 @@ impl StaticTransform {
         let inverse = transform.inverse_transform().unwrap();
         Self { transform, inverse }
     }
+
+    // Transform a surface normal into world space, and work out how much the transform stretches
+    // the surface area around it. Normals need the inverse transpose, and the area of a patch
+    // scales by the determinant multiplied by the length of the transformed normal
+    fn transform_normal(&self, normal: Vector3) -> (Vector3, FloatType) {
+        let transformed = self.inverse.transpose().transform_vector(normal);
+        let area_scale = self.transform.determinant().abs() * transformed.magnitude();
+
+        (transformed.normalize(), area_scale)
+    }
 }
 
 impl GeometryTransform for StaticTransform {
@@ -97,12 +107,18 @@ where
 {
     type Result = <R as Transformable>::Target;
 
-    fn intersect(&self, ray: &Ray, t_min: FloatType, t_max: FloatType) -> Option<Self::Result> {
+    fn intersect(
+        &self,
+        ray: &Ray,
+        t_min: FloatType,
+        t_max: FloatType,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Self::Result> {
         let instant = self.transform.transform_at_t(ray.time());
         let transformed_ray = ray.core_transform(&instant.transform, &instant.inverse);
 
         self.primitive
-            .intersect(&transformed_ray, t_min, t_max)
+            .intersect(&transformed_ray, t_min, t_max, sampler)
             .map(|hit_result| hit_result.core_transform(&instant.transform, &instant.inverse))
     }
 }
@@ -122,6 +138,35 @@ impl<P: TimeDependentBounded> TimeDependentBounded for Transformed<P> {
     }
 }
 
+impl<P: Sampleable> Sampleable for Transformed<P> {
+    fn sample_surface(&self, t: FloatType, sampler: &mut dyn Sampler) -> Option<SurfaceSample> {
+        let instant = self.transform.transform_at_t(t);
+
+        self.primitive.sample_surface(t, sampler).map(|sample| {
+            let (surface_normal, area_scale) = instant.transform_normal(sample.surface_normal);
+
+            SurfaceSample {
+                point: instant.transform.transform_point(sample.point),
+                surface_normal,
+                pdf: sample.pdf / area_scale,
+            }
+        })
+    }
+
+    fn surface_pdf(&self, point: Point3, surface_normal: Vector3, t: FloatType) -> FloatType {
+        let instant = self.transform.transform_at_t(t);
+        let local_point = instant.inverse.transform_point(point);
+        let local_normal = instant
+            .transform
+            .transpose()
+            .transform_vector(surface_normal)
+            .normalize();
+        let (_, area_scale) = instant.transform_normal(local_normal);
+
+        self.primitive.surface_pdf(local_point, local_normal, t) / area_scale
+    }
+}
+
 impl<P> Transformable for Transformed<P> {
     type Target = Self;
 
@@ -192,6 +237,14 @@ impl<P: 'static + Intersectable<Result = SkinnedHitResult> + TimeDependentBounde
             })
             .collect()
     }
+
+    fn is_emissive(&self) -> bool {
+        self.primitive.is_emissive()
+    }
+
+    fn material_description(&self) -> String {
+        self.primitive.material_description()
+    }
 }
 
 pub struct TransformableIterator<P, I: Iterator<Item = P>> {
@@ -1,10 +1,22 @@
 use crate::math::*;
-use random_fast_rng::{local_rng, Random};
+use random_fast_rng::{FastRng, Random};
 
+use std::cell::RefCell;
 use std::convert::TryInto;
 
+// These are for building scenes. Rendering draws its random numbers from a Sampler instead, so
+// that it can be reproduced exactly.
+thread_local! {
+    static LOCAL_RNG: RefCell<FastRng> = RefCell::new(FastRng::new());
+}
+
+// Reseed the random numbers on this thread, so that randomly built scenes come out the same
+pub fn seed_random(seed: u64) {
+    LOCAL_RNG.with(|rng| *rng.borrow_mut() = FastRng::seed(seed, 0));
+}
+
 pub fn random_in_range(min: FloatType, max: FloatType) -> FloatType {
-    (local_rng().gen::<FloatType>() * (max - min)) + min
+    (LOCAL_RNG.with(|rng| rng.borrow_mut().gen::<FloatType>()) * (max - min)) + min
 }
 
 pub fn random_int_in_range(min: i32, max: i32) -> i32 {